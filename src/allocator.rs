@@ -0,0 +1,239 @@
+use crate::component::{
+    ComponentTypeIndex,
+};
+use std::alloc;
+use std::alloc::{
+    Layout,
+};
+use std::cmp;
+use std::collections::{
+    HashMap,
+};
+use std::fmt;
+use std::ptr;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+
+/// The memory allocator backing the component arrays of a storage.
+///
+/// Each call receives the type of the components the memory is for, so an
+/// allocator can attribute memory to component types.
+pub trait ComponentAllocator: Send + Sync {
+    /// Allocate a block of memory for components of the given type. Returns a
+    /// null pointer if the allocation fails.
+    ///
+    /// # Safety
+    ///
+    /// The layout must have a non-zero size.
+    unsafe fn allocate(&self, component_type: ComponentTypeIndex, layout: Layout) -> *mut u8;
+
+    /// Release a block of memory returned by this allocator.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been returned by this allocator for the same
+    /// component type and layout, and must not be used afterwards.
+    unsafe fn deallocate(&self, component_type: ComponentTypeIndex, ptr: *mut u8, layout: Layout);
+
+    /// Resize a block of memory returned by this allocator, keeping its contents
+    /// up to the smaller of the old and new sizes. Returns a null pointer if the
+    /// allocation fails, in which case the old block is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been returned by this allocator for the same
+    /// component type and `old_layout`, and `new_size` must be non-zero.
+    unsafe fn reallocate(
+        &self,
+        component_type: ComponentTypeIndex,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> *mut u8
+    {
+        let new_layout = Layout::from_size_align_unchecked(new_size, old_layout.align());
+        let new_ptr = self.allocate(component_type, new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(old_layout.size(), new_size));
+            self.deallocate(component_type, ptr, old_layout);
+        }
+
+        new_ptr
+    }
+}
+
+impl fmt::Debug for dyn ComponentAllocator {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "ComponentAllocator")
+    }
+}
+
+/// The default allocator, backed by the global allocator.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GlobalAllocator;
+
+impl GlobalAllocator {
+    /// Construct a shared handle to the global allocator.
+    pub fn shared() -> Arc<dyn ComponentAllocator> {
+        Arc::new(GlobalAllocator)
+    }
+}
+
+impl ComponentAllocator for GlobalAllocator {
+    unsafe fn allocate(&self, _component_type: ComponentTypeIndex, layout: Layout) -> *mut u8 {
+        alloc::alloc(layout)
+    }
+
+    unsafe fn deallocate(&self, _component_type: ComponentTypeIndex, ptr: *mut u8, layout: Layout) {
+        alloc::dealloc(ptr, layout)
+    }
+
+    unsafe fn reallocate(
+        &self,
+        _component_type: ComponentTypeIndex,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> *mut u8
+    {
+        alloc::realloc(ptr, old_layout, new_size)
+    }
+}
+
+/// An allocator that records how many bytes are currently allocated for each
+/// component type, forwarding the allocations themselves to another allocator.
+#[derive(Debug, Default)]
+pub struct TrackingAllocator<A = GlobalAllocator> {
+    inner: A,
+    allocated: Mutex<HashMap<ComponentTypeIndex, usize>>,
+}
+
+impl<A> TrackingAllocator<A>
+where
+    A: ComponentAllocator,
+{
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            allocated: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The number of bytes currently allocated for a component type.
+    pub fn allocated_bytes(&self, component_type: ComponentTypeIndex) -> usize {
+        let allocated = self.allocated.lock().unwrap();
+        allocated.get(&component_type).copied().unwrap_or(0)
+    }
+
+    /// The number of bytes currently allocated for all component types.
+    pub fn total_allocated_bytes(&self) -> usize {
+        let allocated = self.allocated.lock().unwrap();
+        allocated.values().sum()
+    }
+
+    fn record(&self, component_type: ComponentTypeIndex, allocated_bytes: usize, released_bytes: usize) {
+        let mut allocated = self.allocated.lock().unwrap();
+        let bytes = allocated.entry(component_type).or_insert(0);
+        *bytes = *bytes + allocated_bytes - released_bytes;
+    }
+}
+
+impl<A> ComponentAllocator for TrackingAllocator<A>
+where
+    A: ComponentAllocator,
+{
+    unsafe fn allocate(&self, component_type: ComponentTypeIndex, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.allocate(component_type, layout);
+        if !ptr.is_null() {
+            self.record(component_type, layout.size(), 0);
+        }
+
+        ptr
+    }
+
+    unsafe fn deallocate(&self, component_type: ComponentTypeIndex, ptr: *mut u8, layout: Layout) {
+        self.inner.deallocate(component_type, ptr, layout);
+        self.record(component_type, 0, layout.size());
+    }
+
+    unsafe fn reallocate(
+        &self,
+        component_type: ComponentTypeIndex,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> *mut u8
+    {
+        let new_ptr = self.inner.reallocate(component_type, ptr, old_layout, new_size);
+        if !new_ptr.is_null() {
+            self.record(component_type, new_size, old_layout.size());
+        }
+
+        new_ptr
+    }
+}
+
+impl<A> ComponentAllocator for Arc<A>
+where
+    A: ComponentAllocator + ?Sized,
+{
+    unsafe fn allocate(&self, component_type: ComponentTypeIndex, layout: Layout) -> *mut u8 {
+        (**self).allocate(component_type, layout)
+    }
+
+    unsafe fn deallocate(&self, component_type: ComponentTypeIndex, ptr: *mut u8, layout: Layout) {
+        (**self).deallocate(component_type, ptr, layout)
+    }
+
+    unsafe fn reallocate(
+        &self,
+        component_type: ComponentTypeIndex,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> *mut u8
+    {
+        (**self).reallocate(component_type, ptr, old_layout, new_size)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::component::{
+        ComponentTypeIndex,
+    };
+    use super::{
+        ComponentAllocator,
+        GlobalAllocator,
+        TrackingAllocator,
+    };
+    use std::alloc::{
+        Layout,
+    };
+
+
+    #[test]
+    fn test_tracking_allocator_records_allocations() {
+        let allocator = TrackingAllocator::new(GlobalAllocator);
+        let component_type = ComponentTypeIndex::of::<u64>();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = allocator.allocate(component_type, layout);
+
+            assert_eq!(allocator.allocated_bytes(component_type), 64);
+
+            let ptr = allocator.reallocate(component_type, ptr, layout, 128);
+
+            assert_eq!(allocator.allocated_bytes(component_type), 128);
+            assert_eq!(allocator.allocated_bytes(ComponentTypeIndex::of::<u32>()), 0);
+
+            allocator.deallocate(component_type, ptr, Layout::from_size_align(128, 8).unwrap());
+        }
+
+        assert_eq!(allocator.total_allocated_bytes(), 0);
+    }
+}
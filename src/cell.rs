@@ -0,0 +1,316 @@
+use crate::storage::{
+    OpaqueComponentStorage,
+};
+use std::cell::{
+    UnsafeCell,
+};
+use std::error;
+use std::fmt;
+use std::ops;
+use std::ptr::{
+    NonNull,
+};
+use std::sync::atomic::{
+    AtomicIsize,
+    Ordering,
+};
+
+
+/// The value of a borrow flag while the storage is borrowed mutably.
+const EXCLUSIVE: isize = -1;
+
+/// The reason a storage could not be borrowed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BorrowError {
+    /// The storage is borrowed mutably, or, for a mutable borrow, borrowed at all.
+    AlreadyBorrowed(&'static str),
+    /// The world has no storage for the component type.
+    MissingStorage(&'static str),
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BorrowError::AlreadyBorrowed(name) => {
+                write!(formatter, "The storage of `{}` is already borrowed", name)
+            }
+            BorrowError::MissingStorage(name) => {
+                write!(formatter, "There is no storage for `{}`", name)
+            }
+        }
+    }
+}
+
+impl error::Error for BorrowError {}
+
+/// A component storage guarded by an atomic borrow flag, so the storage can be
+/// borrowed from several threads at once: any number of shared borrows, or a
+/// single mutable borrow.
+pub(crate) struct StorageCell {
+    borrow: AtomicIsize,
+    storage: UnsafeCell<Box<dyn OpaqueComponentStorage>>,
+}
+
+// SAFETY: The storage is `Send + Sync`, and the borrow flag ensures that a
+// mutable borrow never coexists with any other borrow.
+unsafe impl Sync for StorageCell {}
+
+impl StorageCell {
+    pub(crate) fn new(storage: Box<dyn OpaqueComponentStorage>) -> Self {
+        Self {
+            borrow: AtomicIsize::new(0),
+            storage: UnsafeCell::new(storage),
+        }
+    }
+
+    /// Access the storage without checking the borrow flag. This is always safe,
+    /// since the exclusive reference to the cell rules out any outstanding borrow.
+    pub(crate) fn get_mut(&mut self) -> &mut dyn OpaqueComponentStorage {
+        self.storage.get_mut().as_mut()
+    }
+
+    /// Access the storage without checking the borrow flag.
+    ///
+    /// # Safety
+    ///
+    /// The storage must not be borrowed mutably while the reference is alive.
+    /// This holds, for instance, while the world owning the cell is borrowed
+    /// exclusively.
+    pub(crate) unsafe fn get_unchecked(&self) -> &dyn OpaqueComponentStorage {
+        (*self.storage.get()).as_ref()
+    }
+
+    /// Access the storage mutably without checking the borrow flag.
+    ///
+    /// # Safety
+    ///
+    /// The storage must not be borrowed at all while the reference is alive.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_mut_unchecked(&self) -> &mut dyn OpaqueComponentStorage {
+        (*self.storage.get()).as_mut()
+    }
+
+    pub(crate) fn try_borrow(&self) -> Option<StorageRef<'_, dyn OpaqueComponentStorage>> {
+        let mut current = self.borrow.load(Ordering::Relaxed);
+        loop {
+            if current == EXCLUSIVE {
+                return None;
+            }
+
+            match self.borrow.compare_exchange_weak(current, current + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+
+        let storage = unsafe { (*self.storage.get()).as_ref() };
+
+        Some(StorageRef {
+            value: NonNull::from(storage),
+            borrow: &self.borrow,
+        })
+    }
+
+    pub(crate) fn try_borrow_mut(&self) -> Option<StorageRefMut<'_, dyn OpaqueComponentStorage>> {
+        if self.borrow.compare_exchange(0, EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return None;
+        }
+
+        let storage = unsafe { (*self.storage.get()).as_mut() };
+
+        Some(StorageRefMut {
+            value: NonNull::from(storage),
+            borrow: &self.borrow,
+        })
+    }
+}
+
+/// A shared borrow of a component storage, or of a part of it. The storage is
+/// released when the guard is dropped.
+pub struct StorageRef<'a, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: &'a AtomicIsize,
+}
+
+// SAFETY: The guard only hands out shared references to the value.
+unsafe impl<'a, T: ?Sized + Sync> Send for StorageRef<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for StorageRef<'a, T> {}
+
+impl<'a, T: ?Sized> StorageRef<'a, T> {
+    /// Turn the guard into a guard of a part of the borrowed value.
+    pub fn map<U: ?Sized, F>(guard: StorageRef<'a, T>, f: F) -> StorageRef<'a, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let value = NonNull::from(f(unsafe { guard.value.as_ref() }));
+        let borrow = guard.borrow;
+        std::mem::forget(guard);
+
+        StorageRef {
+            value,
+            borrow,
+        }
+    }
+
+    /// Like [`StorageRef::map`], releasing the borrow if `f` returns `None`.
+    pub fn filter_map<U: ?Sized, F>(guard: StorageRef<'a, T>, f: F) -> Option<StorageRef<'a, U>>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        let value = NonNull::from(f(unsafe { guard.value.as_ref() })?);
+        let borrow = guard.borrow;
+        std::mem::forget(guard);
+
+        Some(StorageRef {
+            value,
+            borrow,
+        })
+    }
+}
+
+impl<'a, T: ?Sized> ops::Deref for StorageRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for StorageRef<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for StorageRef<'a, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, formatter)
+    }
+}
+
+/// A mutable borrow of a component storage, or of a part of it. The storage is
+/// released when the guard is dropped.
+pub struct StorageRefMut<'a, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: &'a AtomicIsize,
+}
+
+// SAFETY: The guard is the only way to reach the value while it is alive.
+unsafe impl<'a, T: ?Sized + Send> Send for StorageRefMut<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for StorageRefMut<'a, T> {}
+
+impl<'a, T: ?Sized> StorageRefMut<'a, T> {
+    /// Turn the guard into a guard of a part of the borrowed value.
+    pub fn map<U: ?Sized, F>(mut guard: StorageRefMut<'a, T>, f: F) -> StorageRefMut<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let value = NonNull::from(f(unsafe { guard.value.as_mut() }));
+        let borrow = guard.borrow;
+        std::mem::forget(guard);
+
+        StorageRefMut {
+            value,
+            borrow,
+        }
+    }
+
+    /// Like [`StorageRefMut::map`], releasing the borrow if `f` returns `None`.
+    pub fn filter_map<U: ?Sized, F>(mut guard: StorageRefMut<'a, T>, f: F) -> Option<StorageRefMut<'a, U>>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let value = NonNull::from(f(unsafe { guard.value.as_mut() })?);
+        let borrow = guard.borrow;
+        std::mem::forget(guard);
+
+        Some(StorageRefMut {
+            value,
+            borrow,
+        })
+    }
+}
+
+impl<'a, T: ?Sized> ops::Deref for StorageRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<'a, T: ?Sized> ops::DerefMut for StorageRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for StorageRefMut<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for StorageRefMut<'a, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, formatter)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::compactable::{
+        CompactableStorage,
+    };
+    use super::{
+        StorageCell,
+        StorageRef,
+    };
+
+
+    #[test]
+    fn test_shared_borrows_exclude_mutable_borrow() {
+        let cell = StorageCell::new(Box::new(CompactableStorage::<usize>::default()));
+        let borrow0 = cell.try_borrow().unwrap();
+        let borrow1 = cell.try_borrow().unwrap();
+
+        assert!(cell.try_borrow_mut().is_none());
+
+        drop(borrow0);
+
+        assert!(cell.try_borrow_mut().is_none());
+
+        drop(borrow1);
+
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn test_mutable_borrow_excludes_all_borrows() {
+        let cell = StorageCell::new(Box::new(CompactableStorage::<usize>::default()));
+        let borrow = cell.try_borrow_mut().unwrap();
+
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+
+        drop(borrow);
+
+        assert!(cell.try_borrow().is_some());
+    }
+
+    #[test]
+    fn test_mapped_borrow_keeps_storage_borrowed() {
+        let cell = StorageCell::new(Box::new(CompactableStorage::<usize>::default()));
+        let borrow = StorageRef::filter_map(cell.try_borrow().unwrap(), |storage| {
+            storage.downcast_ref::<CompactableStorage<usize>>()
+        });
+
+        assert!(borrow.is_some());
+        assert!(cell.try_borrow_mut().is_none());
+
+        drop(borrow);
+
+        assert!(cell.try_borrow_mut().is_some());
+    }
+}
@@ -0,0 +1,732 @@
+use crate::component::{
+    Component,
+    ComponentTypeIndex,
+};
+use crate::allocator::{
+    ComponentAllocator,
+    GlobalAllocator,
+};
+use crate::compactable::{
+    RawComponentArray,
+};
+use crate::storage::{
+    OpaqueComponentStorage,
+    EntityTypeIndex,
+    ComponentMetadata,
+    ComponentIndex,
+};
+use std::cmp;
+use std::marker::{
+    PhantomData,
+};
+use std::mem;
+use std::ptr;
+use std::ptr::{
+    NonNull,
+};
+use std::slice;
+use std::sync::{
+    Arc,
+};
+
+
+/// The default size of a chunk, in bytes.
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+
+/// The components of one entity type, stored in a list of fixed capacity chunks.
+/// Chunks are never reallocated, so a component stays at the same address until
+/// it is moved by a removal.
+#[derive(Debug)]
+struct ChunkedArray<T: Component> {
+    chunks: Vec<RawComponentArray<T>>,
+    chunk_capacity: usize,
+    length: usize,
+    alignment: usize,
+    allocator: Arc<dyn ComponentAllocator>,
+}
+
+impl<T> ChunkedArray<T>
+where
+    T: Component,
+{
+    fn new(chunk_capacity: usize, alignment: usize, allocator: Arc<dyn ComponentAllocator>) -> Self {
+        Self {
+            chunks: Vec::new(),
+            chunk_capacity,
+            length: 0,
+            alignment,
+            allocator,
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn capacity(&self) -> usize {
+        if mem::size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            self.chunks.len() * self.chunk_capacity
+        }
+    }
+
+    #[inline]
+    fn ptr(&self, index: usize) -> *mut T {
+        if mem::size_of::<T>() == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            let chunk = &self.chunks[index / self.chunk_capacity];
+            unsafe {
+                chunk.as_ptr().add(index % self.chunk_capacity)
+            }
+        }
+    }
+
+    /// Return the pointer to and length of an occupied chunk.
+    fn chunk(&self, chunk_index: usize) -> Option<(*mut T, usize)> {
+        let start = chunk_index.checked_mul(self.chunk_capacity)?;
+        if start < self.length {
+            let len = cmp::min(self.chunk_capacity, self.length - start);
+
+            Some((self.ptr(start), len))
+        } else {
+            None
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        while self.capacity() < self.length + additional {
+            let chunk = RawComponentArray::with_capacity(
+                self.chunk_capacity,
+                self.alignment,
+                self.allocator.clone()
+            );
+            self.chunks.push(chunk);
+        }
+    }
+
+    unsafe fn extend_memcopy(&mut self, ptr: *const T, count: usize) {
+        self.reserve(count);
+        let mut copied = 0;
+        while copied < count {
+            // Copy as much as fits into the remainder of the last chunk.
+            let offset = self.length % self.chunk_capacity;
+            let run = cmp::min(self.chunk_capacity - offset, count - copied);
+            ptr::copy_nonoverlapping(ptr.add(copied), self.ptr(self.length), run);
+            self.length += run;
+            copied += run;
+        }
+    }
+
+    fn swap_remove(&mut self, index: usize) -> T {
+        debug_assert!(index < self.length);
+        unsafe {
+            let last_index = self.length - 1;
+            let item_ptr = self.ptr(index);
+            let last_ptr = self.ptr(last_index);
+            if index < last_index {
+                ptr::swap(item_ptr, last_ptr);
+            }
+            let last_value = ptr::read(last_ptr);
+            self.length -= 1;
+
+            last_value
+        }
+    }
+
+    /// Release every chunk that does not contain any components.
+    fn shrink_to_fit(&mut self) {
+        if mem::size_of::<T>() != 0 {
+            let required_chunks = self.length.div_ceil(self.chunk_capacity);
+            self.chunks.truncate(required_chunks);
+        }
+    }
+
+    /// Drop the components whose flag in `removed` is set, moving the others
+    /// down to keep the array packed and in order.
+    fn remove_marked(&mut self, removed: &[bool]) {
+        debug_assert_eq!(removed.len(), self.length);
+        // Empty the array first, so a panicking destructor leaks the remaining
+        // components instead of dropping them twice.
+        self.length = 0;
+        let mut kept = 0;
+        for (index, removed) in removed.iter().enumerate() {
+            unsafe {
+                if *removed {
+                    ptr::drop_in_place(self.ptr(index));
+                } else {
+                    if kept < index {
+                        ptr::copy_nonoverlapping(self.ptr(index), self.ptr(kept), 1);
+                    }
+                    kept += 1;
+                }
+            }
+        }
+        self.length = kept;
+    }
+
+    /// Drop every component in the array, keeping its chunks.
+    fn clear(&mut self) {
+        let len = self.length;
+        // Empty the array first, so a panicking destructor cannot cause a
+        // double drop.
+        self.length = 0;
+        if mem::needs_drop::<T>() {
+            for i in 0..len {
+                unsafe {
+                    ptr::drop_in_place(self.ptr(i));
+                }
+            }
+        }
+    }
+
+    /// Release every chunk without dropping the components in them. This is used
+    /// after the components have been moved out of the array.
+    fn forget_components(&mut self) {
+        self.length = 0;
+        self.chunks.clear();
+    }
+}
+
+impl<T> Drop for ChunkedArray<T>
+where
+    T: Component,
+{
+    fn drop(&mut self) {
+        if mem::needs_drop::<T>() {
+            for i in 0..self.length {
+                unsafe {
+                    ptr::drop_in_place(self.ptr(i));
+                }
+            }
+        }
+    }
+}
+
+
+/// An iterator over the chunks of an entity type's components.
+pub struct ChunkIter<'a, T: Component> {
+    array: Option<&'a ChunkedArray<T>>,
+    chunk_index: usize,
+}
+
+impl<'a, T> Iterator for ChunkIter<'a, T>
+where
+    T: Component,
+{
+    type Item = &'a [T];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (ptr, len) = self.array?.chunk(self.chunk_index)?;
+        self.chunk_index += 1;
+
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+}
+
+/// An iterator over the chunks of an entity type's components, yielding mutable
+/// slices. The chunks are disjoint, so the slices can be processed in parallel.
+pub struct ChunkIterMut<'a, T: Component> {
+    array: Option<NonNull<ChunkedArray<T>>>,
+    chunk_index: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> Iterator for ChunkIterMut<'a, T>
+where
+    T: Component,
+{
+    type Item = &'a mut [T];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let array = unsafe { self.array?.as_ref() };
+        let (ptr, len) = array.chunk(self.chunk_index)?;
+        self.chunk_index += 1;
+
+        Some(unsafe { slice::from_raw_parts_mut(ptr, len) })
+    }
+}
+
+
+/// A component storage that keeps the components of each entity type in fixed
+/// size chunks instead of one reallocating buffer.
+///
+/// Growing an entity type allocates a new chunk instead of reallocating and
+/// moving every component, so components keep their addresses within a chunk,
+/// and each chunk can be processed independently.
+#[derive(Debug)]
+pub struct ChunkedStorage<T: Component> {
+    chunk_capacity: usize,
+    indices: Vec<usize>,
+    components: Vec<ChunkedArray<T>>,
+    alignment: usize,
+    allocator: Arc<dyn ComponentAllocator>,
+}
+
+unsafe impl<T: Component> Send for ChunkedStorage<T> {}
+unsafe impl<T: Component> Sync for ChunkedStorage<T> {}
+
+impl<T> ChunkedStorage<T>
+where
+    T: Component,
+{
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Construct a storage with chunks of approximately `chunk_size` bytes. Each
+    /// chunk holds at least one component.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        let chunk_capacity = if mem::size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            cmp::max(1, chunk_size / mem::size_of::<T>())
+        };
+
+        Self {
+            chunk_capacity,
+            indices: Vec::new(),
+            components: Vec::new(),
+            alignment: mem::align_of::<T>(),
+            allocator: GlobalAllocator::shared(),
+        }
+    }
+
+    /// The number of components in a chunk.
+    pub fn chunk_capacity(&self) -> usize {
+        self.chunk_capacity
+    }
+
+    fn index(&self, entity_type_index: EntityTypeIndex) -> usize {
+        self.indices[entity_type_index.id()]
+    }
+
+    fn array(&self, entity_type: EntityTypeIndex) -> Option<&ChunkedArray<T>> {
+        let array_index = *self.indices.get(entity_type.id())?;
+        self.components.get(array_index)
+    }
+
+    pub fn get(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<&T> {
+        let array = self.array(entity_type)?;
+        if index.id() < array.len() {
+            Some(unsafe { &*array.ptr(index.id()) })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<&mut T> {
+        let array = self.array(entity_type)?;
+        if index.id() < array.len() {
+            Some(unsafe { &mut *array.ptr(index.id()) })
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the chunks of components of an entity type.
+    pub fn chunks(&self, entity_type: EntityTypeIndex) -> ChunkIter<'_, T> {
+        ChunkIter {
+            array: self.array(entity_type),
+            chunk_index: 0,
+        }
+    }
+
+    /// Iterate mutably over the chunks of components of an entity type.
+    pub fn chunks_mut(&mut self, entity_type: EntityTypeIndex) -> ChunkIterMut<'_, T> {
+        let array = match self.indices.get(entity_type.id()) {
+            Some(array_index) => self.components.get_mut(*array_index).map(NonNull::from),
+            None => None,
+        };
+
+        ChunkIterMut {
+            array,
+            chunk_index: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Return the number of entity types in the storage.
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+impl<T> Default for ChunkedStorage<T>
+where
+    T: Component,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> OpaqueComponentStorage for ChunkedStorage<T>
+where
+    T: Component,
+{
+    fn metadata(&self) -> ComponentMetadata {
+        ComponentMetadata::of::<T>()
+    }
+
+    fn component_type(&self) -> ComponentTypeIndex {
+        ComponentTypeIndex::of::<T>()
+    }
+
+    fn set_allocator(&mut self, allocator: Arc<dyn ComponentAllocator>) {
+        self.allocator = allocator;
+    }
+
+    fn new_empty(&self) -> Box<dyn OpaqueComponentStorage> {
+        let mut storage = Self::new();
+        storage.chunk_capacity = self.chunk_capacity;
+        storage.alignment = self.alignment;
+        storage.allocator = self.allocator.clone();
+
+        Box::new(storage)
+    }
+
+    fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    fn set_alignment(&mut self, alignment: usize) -> bool {
+        assert!(alignment.is_power_of_two());
+        if !self.components.is_empty() {
+            return false;
+        }
+
+        self.alignment = cmp::max(alignment, mem::align_of::<T>());
+
+        true
+    }
+
+    fn swap_remove(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex) {
+        let array_index = self.index(entity_type);
+        self.components[array_index].swap_remove(index.id());
+    }
+
+    unsafe fn swap_remove_raw(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex, dst: *mut u8) {
+        let array_index = self.index(entity_type);
+        let component = self.components[array_index].swap_remove(index.id());
+        ptr::write(dst as *mut T, component);
+    }
+
+    fn remove_marked(&mut self, entity_type: EntityTypeIndex, removed: &[bool]) {
+        let array_index = self.index(entity_type);
+        self.components[array_index].remove_marked(removed);
+    }
+
+    fn count(&self, entity_type: EntityTypeIndex) -> Option<usize> {
+        self.array(entity_type).map(|array| array.len())
+    }
+
+    fn run_len(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> usize {
+        let len = self.count(entity_type).unwrap_or(0).saturating_sub(index.id());
+        // A run ends at the end of the chunk holding `index`.
+        cmp::min(len, self.chunk_capacity - index.id() % self.chunk_capacity)
+    }
+
+    fn get_bytes(&self, _entity_type: EntityTypeIndex) -> Option<(*const u8, usize)> {
+        // The components of an entity type are not contiguous.
+        None
+    }
+
+    unsafe fn get_bytes_mut(&mut self, _entity_type: EntityTypeIndex) -> Option<(*mut u8, usize)> {
+        None
+    }
+
+    fn get_component_ptr(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*const u8> {
+        let array = self.array(entity_type)?;
+        // Derive the pointer from the chunk, so it is valid for the rest of it.
+        (index.id() < array.len()).then(|| array.ptr(index.id()) as *const u8)
+    }
+
+    unsafe fn get_component_ptr_mut(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*mut u8> {
+        let array = self.array(entity_type)?;
+        (index.id() < array.len()).then(|| array.ptr(index.id()) as *mut u8)
+    }
+
+    unsafe fn extend_memcopy_raw(&mut self, entity_type: EntityTypeIndex, ptr: *const u8, count: usize) {
+        let array_index = self.index(entity_type);
+        self.components[array_index].extend_memcopy(ptr as *const T, count);
+    }
+
+    fn transfer_entity_type(
+        &mut self,
+        src: EntityTypeIndex,
+        dst: EntityTypeIndex,
+        dst_storage: &mut dyn OpaqueComponentStorage,
+    ) {
+        let src_index = self.index(src);
+        if let Some(dst_chunked_storage) = dst_storage.downcast_mut::<Self>() {
+            let dst_index = dst_chunked_storage.index(dst);
+            let dst_components = &mut dst_chunked_storage.components[dst_index];
+            if dst_components.is_empty()
+                && dst_components.chunk_capacity == self.chunk_capacity
+                && dst_components.alignment <= self.components[src_index].alignment
+            {
+                // There is nothing to append to, so we can hand the chunks over
+                // directly. The source starts over with its own alignment and allocator.
+                let empty = ChunkedArray::new(self.chunk_capacity, self.alignment, self.allocator.clone());
+                *dst_components = mem::replace(&mut self.components[src_index], empty);
+
+                return;
+            }
+        }
+
+        let src_components = &mut self.components[src_index];
+        let mut chunk_index = 0;
+        while let Some((ptr, len)) = src_components.chunk(chunk_index) {
+            unsafe {
+                dst_storage.extend_memcopy_raw(dst, ptr as *const u8, len);
+            }
+            chunk_index += 1;
+        }
+
+        // The components belong to the destination storage now.
+        src_components.forget_components();
+    }
+
+    fn transfer_component(
+        &mut self,
+        src: EntityTypeIndex,
+        src_component: ComponentIndex,
+        dst: EntityTypeIndex,
+        dst_storage: &mut dyn OpaqueComponentStorage,
+    ) {
+        let src_index = self.index(src);
+        let component = self.components[src_index].swap_remove(src_component.id());
+        unsafe {
+            dst_storage.extend_memcopy_raw(
+                dst,
+                &component as *const T as *const u8,
+                1
+            );
+        }
+        mem::forget(component);
+    }
+
+    fn move_component(
+        &mut self,
+        src: EntityTypeIndex,
+        index: ComponentIndex,
+        dst: EntityTypeIndex,
+    ) {
+        let src_index = self.index(src);
+        let dst_index = self.index(dst);
+        let value = self.components[src_index].swap_remove(index.id());
+        unsafe {
+            self.components[dst_index].extend_memcopy(&value as *const T, 1);
+        }
+        mem::forget(value);
+    }
+
+    fn insert_entity_type(&mut self, entity_type_index: EntityTypeIndex) {
+        let array_index = self.components.len();
+        self.components.push(ChunkedArray::new(self.chunk_capacity, self.alignment, self.allocator.clone()));
+
+        if entity_type_index.id() >= self.indices.len() {
+            self.indices.resize(entity_type_index.id() + 1, usize::MAX);
+        }
+
+        self.indices[entity_type_index.id()] = array_index;
+    }
+
+    fn remap_entity_types(&mut self, remap: &[Option<EntityTypeIndex>]) {
+        let old_indices = mem::take(&mut self.indices);
+        let mut old_components: Vec<Option<ChunkedArray<T>>> = mem::take(&mut self.components)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        for (old_id, old_array_index) in old_indices.iter().enumerate() {
+            if *old_array_index == usize::MAX {
+                continue;
+            }
+
+            let array = old_components[*old_array_index].take().unwrap();
+            if let Some(new_index) = remap.get(old_id).copied().flatten() {
+                let array_index = self.components.len();
+                self.components.push(array);
+                if new_index.id() >= self.indices.len() {
+                    self.indices.resize(new_index.id() + 1, usize::MAX);
+                }
+                self.indices[new_index.id()] = array_index;
+            }
+        }
+    }
+
+    fn capacity(&self, entity_type: EntityTypeIndex) -> Option<usize> {
+        self.array(entity_type).map(|array| array.capacity())
+    }
+
+    fn shrink_to_fit(&mut self, entity_type: EntityTypeIndex) {
+        let array_index = self.index(entity_type);
+        self.components[array_index].shrink_to_fit();
+    }
+
+    fn clear(&mut self) {
+        for array in self.components.iter_mut() {
+            array.clear();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        EntityTypeIndex,
+        OpaqueComponentStorage,
+        ComponentIndex,
+    };
+    use super::{
+        ChunkedStorage,
+    };
+    use std::mem;
+    use std::sync::{
+        Arc,
+    };
+
+
+    fn extend(storage: &mut ChunkedStorage<usize>, entity_type: EntityTypeIndex, mut components: Vec<usize>) {
+        unsafe {
+            storage.extend_memcopy_raw(entity_type, components.as_ptr() as *const u8, components.len());
+            components.set_len(0);
+        }
+    }
+
+    #[test]
+    fn test_chunked_storage_chunk_capacity() {
+        let storage = ChunkedStorage::<u64>::with_chunk_size(64);
+
+        assert_eq!(storage.chunk_capacity(), 8);
+    }
+
+    #[test]
+    fn test_chunked_storage_extend_across_chunks() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(3 * mem::size_of::<usize>());
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1, 2, 3, 4, 5, 6]);
+        let chunks: Vec<&[usize]> = storage.chunks(entity_type).collect();
+        let expected: Vec<&[usize]> = vec![&[0, 1, 2], &[3, 4, 5], &[6]];
+
+        assert_eq!(chunks, expected);
+        assert_eq!(storage.count(entity_type), Some(7));
+        assert_eq!(storage.capacity(entity_type), Some(9));
+        assert_eq!(storage.get(entity_type, ComponentIndex::new(4)), Some(&4));
+        assert_eq!(storage.get(entity_type, ComponentIndex::new(7)), None);
+    }
+
+    #[test]
+    fn test_chunked_storage_addresses_are_stable_on_growth() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1]);
+        let address = storage.get(entity_type, ComponentIndex::new(1)).unwrap() as *const usize;
+        extend(&mut storage, entity_type, (2..100).collect());
+
+        assert_eq!(storage.get(entity_type, ComponentIndex::new(1)).unwrap() as *const usize, address);
+    }
+
+    #[test]
+    fn test_chunked_storage_swap_remove_and_shrink() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1, 2, 3, 4]);
+        storage.swap_remove(entity_type, ComponentIndex::new(0));
+        storage.swap_remove(entity_type, ComponentIndex::new(0));
+        storage.shrink_to_fit(entity_type);
+        let components: Vec<usize> = storage.chunks(entity_type).flatten().copied().collect();
+
+        assert_eq!(components, vec![3, 1, 2]);
+        assert_eq!(storage.capacity(entity_type), Some(4));
+    }
+
+    #[test]
+    fn test_chunked_storage_chunks_mut() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1, 2, 3, 4]);
+        for chunk in storage.chunks_mut(entity_type) {
+            for component in chunk.iter_mut() {
+                *component *= 2;
+            }
+        }
+        let components: Vec<usize> = storage.chunks(entity_type).flatten().copied().collect();
+
+        assert_eq!(components, vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_chunked_storage_transfer_entity_type() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let mut dst_storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        dst_storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1, 2]);
+        extend(&mut dst_storage, entity_type, vec![3]);
+        storage.transfer_entity_type(entity_type, entity_type, &mut dst_storage);
+        let components: Vec<usize> = dst_storage.chunks(entity_type).flatten().copied().collect();
+
+        assert_eq!(storage.count(entity_type), Some(0));
+        assert_eq!(components, vec![3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_chunked_storage_transfer_into_empty_keeps_source_alignment() {
+        let mut storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        let mut dst_storage = ChunkedStorage::<usize>::with_chunk_size(2 * mem::size_of::<usize>());
+        assert!(storage.set_alignment(64));
+        let entity_type = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type);
+        dst_storage.insert_entity_type(entity_type);
+        extend(&mut storage, entity_type, vec![0, 1, 2]);
+        storage.transfer_entity_type(entity_type, entity_type, &mut dst_storage);
+        extend(&mut storage, entity_type, vec![3, 4, 5]);
+        let components: Vec<usize> = dst_storage.chunks(entity_type).flatten().copied().collect();
+
+        assert_eq!(components, vec![0, 1, 2]);
+        for chunk in storage.chunks(entity_type) {
+            assert_eq!(chunk.as_ptr() as usize % 64, 0);
+        }
+    }
+
+    #[test]
+    fn test_chunked_storage_drops_components() {
+        let value = Arc::new(());
+        {
+            let mut storage = ChunkedStorage::<Arc<()>>::with_chunk_size(2 * mem::size_of::<Arc<()>>());
+            let entity_type = EntityTypeIndex::new(0);
+            storage.insert_entity_type(entity_type);
+            let mut components = vec![value.clone(), value.clone(), value.clone()];
+            unsafe {
+                storage.extend_memcopy_raw(entity_type, components.as_ptr() as *const u8, 3);
+                components.set_len(0);
+            }
+            storage.swap_remove(entity_type, ComponentIndex::new(1));
+
+            assert_eq!(Arc::strong_count(&value), 3);
+        }
+
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
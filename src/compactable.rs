@@ -1,20 +1,25 @@
 use crate::component::{
     Component,
+    ComponentTypeIndex,
 };
-use crate::entity::{
-    Entity,
+use crate::allocator::{
+    ComponentAllocator,
+    GlobalAllocator,
 };
 use crate::storage::{
     OpaqueComponentStorage,
     ComponentStorage,
     EntityTypeIndex,
-    EntityLocation,
     ComponentView,
     ComponentViewMut,
     ComponentMetadata,
     ComponentIndex,
 };
 use std::alloc;
+use std::cmp;
+use std::marker::{
+    PhantomData,
+};
 use std::mem;
 use std::ops;
 use std::ptr;
@@ -22,121 +27,191 @@ use std::ptr::{
     NonNull,
 };
 use std::slice;
+use std::sync::{
+    Arc,
+};
 use std::slice::{
     Iter,
-    IterMut,
 };
 
 #[derive(Debug)]
-struct RawComponentArray<T> {
+pub(crate) struct RawComponentArray<T: Component> {
     ptr: NonNull<T>,
     capacity: usize,
+    alignment: usize,
+    allocator: Arc<dyn ComponentAllocator>,
 }
 
-impl<T> RawComponentArray<T> {
-    fn with_capacity(capacity: usize) -> Self {
+impl<T> RawComponentArray<T>
+where
+    T: Component,
+{
+    /// Allocate an array for `capacity` elements whose buffer is aligned to at
+    /// least `alignment` bytes. The alignment must be a power of two.
+    pub(crate) fn with_capacity(
+        capacity: usize,
+        alignment: usize,
+        allocator: Arc<dyn ComponentAllocator>
+    ) -> Self
+    {
+        let alignment = cmp::max(alignment, mem::align_of::<T>());
         if mem::size_of::<T>() == 0 {
             Self {
-                ptr: NonNull::dangling(),
+                ptr: Self::dangling(alignment),
                 capacity: usize::MAX,
+                alignment,
+                allocator,
             }
         } else if capacity == 0 {
             Self {
-                ptr: NonNull::dangling(),
+                ptr: Self::dangling(alignment),
                 capacity: 0,
+                alignment,
+                allocator,
             }
         } else {
-            let layout = alloc::Layout::from_size_align(
-                mem::size_of::<T>() * capacity, 
-                mem::align_of::<T>()
-            )
-            .unwrap();
-            
+            let layout = Self::layout(capacity, alignment);
             let raw_ptr = unsafe {
-                alloc::alloc(layout) as *mut T
+                allocator.allocate(ComponentTypeIndex::of::<T>(), layout) as *mut T
+            };
+            let ptr = match NonNull::new(raw_ptr) {
+                Some(ptr) => ptr,
+                None => alloc::handle_alloc_error(layout),
             };
 
             Self {
-                ptr: NonNull::new(raw_ptr).unwrap(),
-                capacity: capacity,
+                ptr,
+                capacity,
+                alignment,
+                allocator,
             }
         }
     }
 
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// A dangling pointer that respects the requested alignment, so that even
+    /// empty arrays keep the alignment guarantee.
+    fn dangling(alignment: usize) -> NonNull<T> {
+        NonNull::new(ptr::without_provenance_mut(alignment)).unwrap()
+    }
+
+    fn layout(capacity: usize, alignment: usize) -> alloc::Layout {
+        alloc::Layout::from_size_align(
+            mem::size_of::<T>() * capacity,
+            alignment
+        )
+        .unwrap()
+    }
+
     fn grow(&mut self, new_capacity: usize) {
         debug_assert!(self.capacity < new_capacity);
+        let component_type = ComponentTypeIndex::of::<T>();
         unsafe {
             let dst_ptr = if self.capacity == 0 {
                 // If the old capacity is zero, we allocated zero space in the old allocation.
-                let layout = alloc::Layout::from_size_align(
-                    mem::size_of::<T>() * new_capacity,
-                    mem::align_of::<T>()
-                )
-                .unwrap();
-                let new_allocation = alloc::alloc(layout);
-                
+                let new_allocation = self.allocator.allocate(
+                    component_type,
+                    Self::layout(new_capacity, self.alignment)
+                );
+
                 new_allocation as *mut T
             } else {
-                let layout = alloc::Layout::from_size_align(
-                    mem::size_of::<T>() * new_capacity, 
-                    mem::align_of::<T>()
-                )
-                .unwrap();
-
-                let new_allocation = alloc::realloc(
+                let new_allocation = self.allocator.reallocate(
+                    component_type,
                     self.ptr.as_ptr() as *mut u8,
-                    layout,
+                    Self::layout(self.capacity, self.alignment),
                     mem::size_of::<T>() * new_capacity
                 );
-                
+
                 new_allocation as *mut T
             };
             if let Some(new_ptr) = NonNull::new(dst_ptr) {
                 self.ptr = new_ptr;
                 self.capacity = new_capacity;
             } else {
-                let layout = alloc::Layout::from_size_align_unchecked(
-                    mem::size_of::<T>() * new_capacity, 
-                    mem::align_of::<T>()
-                );
+                alloc::handle_alloc_error(Self::layout(new_capacity, self.alignment))
+            }
+        }
+    }
+
+    /// Release the part of the allocation beyond `new_capacity` elements.
+    fn shrink(&mut self, new_capacity: usize) {
+        debug_assert!(new_capacity <= self.capacity);
+        if (mem::size_of::<T>() == 0) || (new_capacity == self.capacity) {
+            return;
+        }
 
-                alloc::handle_alloc_error(layout)
+        let component_type = ComponentTypeIndex::of::<T>();
+        unsafe {
+            if new_capacity == 0 {
+                self.allocator.deallocate(
+                    component_type,
+                    self.ptr.as_ptr() as *mut u8,
+                    Self::layout(self.capacity, self.alignment)
+                );
+                self.ptr = Self::dangling(self.alignment);
+                self.capacity = 0;
+            } else {
+                let new_allocation = self.allocator.reallocate(
+                    component_type,
+                    self.ptr.as_ptr() as *mut u8,
+                    Self::layout(self.capacity, self.alignment),
+                    mem::size_of::<T>() * new_capacity
+                );
+                if let Some(new_ptr) = NonNull::new(new_allocation as *mut T) {
+                    self.ptr = new_ptr;
+                    self.capacity = new_capacity;
+                } else {
+                    alloc::handle_alloc_error(Self::layout(new_capacity, self.alignment))
+                }
             }
         }
     }
 }
 
-impl<T> Drop for RawComponentArray<T> {
+impl<T> Drop for RawComponentArray<T>
+where
+    T: Component,
+{
     fn drop(&mut self) {
         if (mem::size_of::<T>() != 0) && (self.capacity > 0) {
             unsafe {
-                let layout = alloc::Layout::from_size_align_unchecked(
-                    mem::size_of::<T>() * self.capacity,
-                    mem::align_of::<T>(),
+                self.allocator.deallocate(
+                    ComponentTypeIndex::of::<T>(),
+                    self.ptr.as_ptr() as *mut u8,
+                    Self::layout(self.capacity, self.alignment)
                 );
-
-                alloc::dealloc(self.ptr.as_ptr() as *mut u8, layout);
             }
         }
     }
 }
 
 #[derive(Debug)]
-struct ComponentArray<T> {
+struct ComponentArray<T: Component> {
     inner: RawComponentArray<T>,
     length: usize,
-    capacity: usize,
 }
 
-impl<T> ComponentArray<T> {
-    fn new() -> Self {
+impl<T> ComponentArray<T>
+where
+    T: Component,
+{
+    fn new(alignment: usize, allocator: Arc<dyn ComponentAllocator>) -> Self {
         Self {
-            inner: RawComponentArray::with_capacity(0),
+            inner: RawComponentArray::with_capacity(0, alignment, allocator),
             length: 0,
-            capacity: 0,
         }
     }
 
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
     fn swap_remove(&mut self, index: usize) -> T {
         let (ptr, len) = self.as_raw_slice();
         debug_assert!(index < len);
@@ -145,9 +220,9 @@ impl<T> ComponentArray<T> {
             let last_ptr = ptr.as_ptr().add(len - 1);
             if index < len - 1 {
                 // We are removing an item from the middle of the array. If
-                // we were removing the last item from the array (i.e. the item at 
-                // array position len - 1), there would be no need to swap to keep the 
-                // array packed, so we can just remove the last item from the 
+                // we were removing the last item from the array (i.e. the item at
+                // array position len - 1), there would be no need to swap to keep the
+                // array packed, so we can just remove the last item from the
                 // array and return it.
                 ptr::swap(item_ptr, last_ptr);
             }
@@ -173,8 +248,50 @@ impl<T> ComponentArray<T> {
     }
 
     fn reserve(&mut self, additonal: usize) {
-        if self.capacity < self.length + additonal {
-            self.grow(self.length + additonal);
+        let required = self.length + additonal;
+        if self.capacity() < required {
+            self.grow(cmp::max(required, 2 * self.capacity()));
+        }
+    }
+
+    /// Release any capacity beyond the length of the array.
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink(self.length);
+    }
+
+    /// Drop the components whose flag in `removed` is set, moving the others
+    /// down to keep the array packed and in order.
+    fn remove_marked(&mut self, removed: &[bool]) {
+        let (ptr, len) = self.as_raw_slice();
+        debug_assert_eq!(removed.len(), len);
+        // Empty the array first, so a panicking destructor leaks the remaining
+        // components instead of dropping them twice.
+        self.length = 0;
+        let mut kept = 0;
+        for (index, removed) in removed.iter().enumerate() {
+            unsafe {
+                let item_ptr = ptr.as_ptr().add(index);
+                if *removed {
+                    ptr::drop_in_place(item_ptr);
+                } else {
+                    if kept < index {
+                        ptr::copy_nonoverlapping(item_ptr, ptr.as_ptr().add(kept), 1);
+                    }
+                    kept += 1;
+                }
+            }
+        }
+        self.length = kept;
+    }
+
+    /// Drop every component in the array, keeping its capacity.
+    fn clear(&mut self) {
+        let (ptr, len) = self.as_raw_slice();
+        // Empty the array first, so a panicking destructor cannot cause a
+        // double drop.
+        self.length = 0;
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len));
         }
     }
 
@@ -186,7 +303,10 @@ impl<T> ComponentArray<T> {
     }
 }
 
-impl<T> ops::Deref for ComponentArray<T> {
+impl<T> ops::Deref for ComponentArray<T>
+where
+    T: Component,
+{
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
@@ -197,7 +317,10 @@ impl<T> ops::Deref for ComponentArray<T> {
     }
 }
 
-impl<T> ops::DerefMut for ComponentArray<T> {
+impl<T> ops::DerefMut for ComponentArray<T>
+where
+    T: Component,
+{
     fn deref_mut(&mut self) -> &mut Self::Target {
         let (ptr, len) = self.as_raw_slice();
         unsafe {
@@ -206,7 +329,10 @@ impl<T> ops::DerefMut for ComponentArray<T> {
     }
 }
 
-impl<T> Drop for ComponentArray<T> {
+impl<T> Drop for ComponentArray<T>
+where
+    T: Component,
+{
     fn drop(&mut self) {
         if mem::needs_drop::<T>() {
             unsafe {
@@ -222,9 +348,10 @@ impl<T> Drop for ComponentArray<T> {
 
 pub struct ComponentIter<'a, T> {
     iter: Iter<'a, (NonNull<T>, usize)>,
+    alignment: usize,
 }
 
-impl<'a, T> Iterator for ComponentIter<'a, T> 
+impl<'a, T> Iterator for ComponentIter<'a, T>
 where
     T: Component,
 {
@@ -236,13 +363,17 @@ where
                 slice::from_raw_parts(ptr.as_ptr(), *len)
             };
 
-            ComponentView::new(view)
+            ComponentView::new(view, self.alignment)
         })
     }
 }
 
+/// Iterates over the views of a storage borrowed exclusively. Every view lives in
+/// its own allocation, so the views handed out never overlap.
 pub struct ComponentIterMut<'a, T> {
     iter: Iter<'a, (NonNull<T>, usize)>,
+    alignment: usize,
+    _marker: PhantomData<&'a mut T>,
 }
 
 impl<'a, T> Iterator for ComponentIterMut<'a, T>
@@ -257,7 +388,7 @@ where
                 slice::from_raw_parts_mut(ptr.as_ptr(), *len)
             };
 
-            ComponentViewMut::new(view)
+            ComponentViewMut::new(view, self.alignment)
         })
     }
 }
@@ -268,6 +399,8 @@ pub struct CompactableStorage<T: Component> {
     indices: Vec<usize>,
     views: Vec<(NonNull<T>, usize)>,
     components: Vec<ComponentArray<T>>,
+    alignment: usize,
+    allocator: Arc<dyn ComponentAllocator>,
 }
 
 unsafe impl<T: Component> Send for CompactableStorage<T> {}
@@ -278,8 +411,8 @@ where
     T: Component,
 {
     fn swap_remove_internal(
-        &mut self, 
-        entity_type: EntityTypeIndex, 
+        &mut self,
+        entity_type: EntityTypeIndex,
         index: ComponentIndex
     ) -> T
     {
@@ -301,7 +434,7 @@ where
     }
 }
 
-impl<T> Default for CompactableStorage<T> 
+impl<T> Default for CompactableStorage<T>
 where
     T: Component,
 {
@@ -311,6 +444,8 @@ where
             indices: Vec::new(),
             views: Vec::new(),
             components: Vec::new(),
+            alignment: mem::align_of::<T>(),
+            allocator: GlobalAllocator::shared(),
         }
     }
 }
@@ -327,6 +462,20 @@ where
         self.swap_remove_internal(entity_type, index);
     }
 
+    unsafe fn swap_remove_raw(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex, dst: *mut u8) {
+        let component = self.swap_remove_internal(entity_type, index);
+        ptr::write(dst as *mut T, component);
+    }
+
+    fn remove_marked(&mut self, entity_type: EntityTypeIndex, removed: &[bool]) {
+        let view_index = self.index(entity_type);
+        let allocation = &mut self.components[view_index];
+        let len = allocation.length;
+        allocation.remove_marked(removed);
+        self.length -= len - allocation.length;
+        self.update_view(view_index);
+    }
+
     fn get_bytes(&self, entity_type: EntityTypeIndex) -> Option<(*const u8, usize)> {
         let view_index = *self.indices.get(entity_type.id())?;
         let (ptr, len_bytes) = self.views.get(view_index)?;
@@ -382,17 +531,90 @@ where
         let component = self.swap_remove_internal(src, src_component);
         unsafe {
             dst_storage.extend_memcopy_raw(
-                dst, 
-                &component as *const T as *const u8, 
+                dst,
+                &component as *const T as *const u8,
                 1
             );
         }
         mem::forget(component);
     }
 
+    fn component_type(&self) -> ComponentTypeIndex {
+        ComponentTypeIndex::of::<T>()
+    }
+
+    fn set_allocator(&mut self, allocator: Arc<dyn ComponentAllocator>) {
+        self.allocator = allocator;
+    }
+
+    fn new_empty(&self) -> Box<dyn OpaqueComponentStorage> {
+        Box::new(Self {
+            alignment: self.alignment,
+            allocator: self.allocator.clone(),
+            ..Self::default()
+        })
+    }
+
+    fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    fn set_alignment(&mut self, alignment: usize) -> bool {
+        assert!(alignment.is_power_of_two());
+        if !self.components.is_empty() {
+            return false;
+        }
+
+        self.alignment = cmp::max(alignment, mem::align_of::<T>());
+
+        true
+    }
+
+    fn count(&self, entity_type: EntityTypeIndex) -> Option<usize> {
+        let view_index = *self.indices.get(entity_type.id())?;
+        self.views.get(view_index).map(|(_, len)| *len)
+    }
+
+    fn run_len(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> usize {
+        self.count(entity_type).unwrap_or(0).saturating_sub(index.id())
+    }
+
+    fn get_component_ptr(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*const u8> {
+        let view_index = *self.indices.get(entity_type.id())?;
+        let (ptr, len) = self.views.get(view_index)?;
+        if index.id() < *len {
+            Some(unsafe { ptr.as_ptr().add(index.id()) as *const u8 })
+        } else {
+            None
+        }
+    }
+
+    unsafe fn get_component_ptr_mut(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*mut u8> {
+        self.get_component_ptr(entity_type, index).map(|ptr| ptr as *mut u8)
+    }
+
+    fn capacity(&self, entity_type: EntityTypeIndex) -> Option<usize> {
+        let view_index = *self.indices.get(entity_type.id())?;
+        self.components.get(view_index).map(|components| components.capacity())
+    }
+
+    fn shrink_to_fit(&mut self, entity_type: EntityTypeIndex) {
+        let view_index = self.index(entity_type);
+        self.components[view_index].shrink_to_fit();
+        self.update_view(view_index);
+    }
+
+    fn clear(&mut self) {
+        for view_index in 0..self.components.len() {
+            self.components[view_index].clear();
+            self.update_view(view_index);
+        }
+        self.length = 0;
+    }
+
     fn insert_entity_type(&mut self, entity_type_index: EntityTypeIndex) {
         let view_index = self.views.len();
-        let component_array = ComponentArray::<T>::new();
+        let component_array = ComponentArray::<T>::new(self.alignment, self.allocator.clone());
 
         self.views.insert(view_index, component_array.as_raw_slice());
         self.components.insert(view_index, component_array);
@@ -404,36 +626,84 @@ where
         self.indices[entity_type_index.id()] = view_index;
     }
 
+    fn remap_entity_types(&mut self, remap: &[Option<EntityTypeIndex>]) {
+        let old_indices = mem::take(&mut self.indices);
+        let mut old_components: Vec<Option<ComponentArray<T>>> = mem::take(&mut self.components)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.views.clear();
+
+        for (old_id, old_view_index) in old_indices.iter().enumerate() {
+            if *old_view_index == usize::MAX {
+                // The entity type does not have components in this storage.
+                continue;
+            }
+
+            let component_array = old_components[*old_view_index].take().unwrap();
+            if let Some(new_index) = remap.get(old_id).copied().flatten() {
+                let view_index = self.components.len();
+                self.views.push(component_array.as_raw_slice());
+                self.components.push(component_array);
+                if new_index.id() >= self.indices.len() {
+                    self.indices.resize(new_index.id() + 1, usize::MAX);
+                }
+                self.indices[new_index.id()] = view_index;
+            } else {
+                self.length -= component_array.len();
+            }
+        }
+    }
+
     fn transfer_entity_type(
         &mut self,
-        src: EntityTypeIndex, 
-        dst: EntityTypeIndex, 
+        src: EntityTypeIndex,
+        dst: EntityTypeIndex,
         dst_opaque_storage: &mut dyn OpaqueComponentStorage,
     ) {
-        let dst_storage = dst_opaque_storage.downcast_mut::<Self>().unwrap();
         let src_index = self.index(src);
-        let dst_index = dst_storage.index(dst);
-
         let entity_count = self.components[src_index].len();
         self.length -= entity_count;
+
+        let dst_storage = match dst_opaque_storage.downcast_mut::<Self>() {
+            Some(dst_storage) => dst_storage,
+            None => {
+                // The destination uses another storage backend, so we can only
+                // hand it the components by copy.
+                let src_components = &mut self.components[src_index];
+                let (ptr, len) = src_components.as_raw_slice();
+                unsafe {
+                    dst_opaque_storage.extend_memcopy_raw(dst, ptr.as_ptr() as *const u8, len);
+                }
+                src_components.length = 0;
+                src_components.shrink_to_fit();
+                self.update_view(src_index);
+
+                return;
+            }
+        };
+        let dst_index = dst_storage.index(dst);
         dst_storage.length += entity_count;
 
-        if dst_storage.components[dst_index].len() == 0 {
+        let src_alignment = self.components[src_index].inner.alignment;
+        let dst_alignment = dst_storage.components[dst_index].inner.alignment;
+        if dst_storage.components[dst_index].is_empty() && src_alignment >= dst_alignment {
             // If the component array is empty, there is nothing to transfer,
-            // so we can just swap the arrays directly.
-            mem::swap(
-                &mut self.components[src_index], 
-                &mut dst_storage.components[dst_index]
-            );
+            // so we can hand the whole source array to the destination. The
+            // source starts over with an array of its own alignment and allocator.
+            let empty = ComponentArray::new(self.alignment, self.allocator.clone());
+            dst_storage.components[dst_index] = mem::replace(&mut self.components[src_index], empty);
         } else {
-            let (ptr, len) = self.get_bytes(src).unwrap();
+            let src_components = &mut self.components[src_index];
+            let (ptr, len) = src_components.as_raw_slice();
             unsafe {
-                dst_storage.extend_memcopy_raw(dst, ptr, len);
+                dst_storage.components[dst_index].extend_memcopy(ptr.as_ptr(), len);
             }
 
-            let mut swapped_components = ComponentArray::<T>::new();
-            mem::swap(&mut self.components[src_index], &mut swapped_components);
-            mem::forget(swapped_components);
+            // The components belong to the destination storage now, so the source
+            // array must not drop them when we release its allocation.
+            src_components.length = 0;
+            src_components.shrink_to_fit();
         }
 
         self.update_view(src_index);
@@ -442,7 +712,7 @@ where
 }
 
 impl<'a, T> ComponentStorage<'a, T> for CompactableStorage<T>
-where 
+where
     T: Component,
 {
     type Iter = ComponentIter<'a, T>;
@@ -455,17 +725,44 @@ where
             slice::from_raw_parts(ptr.as_ptr(), *len)
         };
 
-        Some(ComponentView::new(view))
+        Some(ComponentView::new(view, self.alignment))
     }
 
-    fn get_mut(&'a self, entity_type: EntityTypeIndex) -> Option<ComponentViewMut<'a, T>> {
+    fn get_mut(&'a mut self, entity_type: EntityTypeIndex) -> Option<ComponentViewMut<'a, T>> {
         let view_index = *self.indices.get(entity_type.id())?;
         let (ptr, len) = self.views.get(view_index)?;
         let view = unsafe {
             slice::from_raw_parts_mut(ptr.as_ptr(), *len)
         };
 
-        Some(ComponentViewMut::new(view))
+        Some(ComponentViewMut::new(view, self.alignment))
+    }
+
+    fn get_disjoint_mut(&'a mut self, entity_types: &[EntityTypeIndex]) -> Vec<Option<ComponentViewMut<'a, T>>> {
+        let mut view_indices: Vec<usize> = entity_types
+            .iter()
+            .filter_map(|entity_type| self.indices.get(entity_type.id()).copied())
+            .filter(|view_index| *view_index < self.views.len())
+            .collect();
+        let len = view_indices.len();
+        view_indices.sort_unstable();
+        view_indices.dedup();
+        assert_eq!(view_indices.len(), len, "An entity type is listed twice");
+
+        entity_types
+            .iter()
+            .map(|entity_type| {
+                let view_index = *self.indices.get(entity_type.id())?;
+                let (ptr, len) = self.views.get(view_index)?;
+                // Every view lives in its own allocation, and every view is
+                // handed out at most once.
+                let view = unsafe {
+                    slice::from_raw_parts_mut(ptr.as_ptr(), *len)
+                };
+
+                Some(ComponentViewMut::new(view, self.alignment))
+            })
+            .collect()
     }
 
     unsafe fn extend_memcopy(&mut self, entity_type: EntityTypeIndex, ptr: *const T, len: usize) {
@@ -475,12 +772,15 @@ where
     fn iter(&'a self) -> Self::Iter {
         ComponentIter {
             iter: self.views[..].iter(),
+            alignment: self.alignment,
         }
     }
 
-    fn iter_mut(&'a self) -> Self::IterMut {
+    fn iter_mut(&'a mut self) -> Self::IterMut {
         ComponentIterMut {
             iter: self.views[..].iter(),
+            alignment: self.alignment,
+            _marker: PhantomData,
         }
     }
 
@@ -497,11 +797,14 @@ mod tests {
         OpaqueComponentStorage,
         ComponentStorage,
         ComponentIndex,
+        ComponentMetadata,
     };
     use super::{
         CompactableStorage,
     };
-    use std::mem;
+    use std::sync::{
+        Arc,
+    };
 
 
     #[test]
@@ -511,6 +814,14 @@ mod tests {
         assert!(storage.is_empty());
     }
 
+    #[test]
+    fn test_metadata_compares_component_types() {
+        let storage = CompactableStorage::<String>::default();
+
+        assert!(storage.metadata() == ComponentMetadata::of::<String>());
+        assert!(storage.metadata() != ComponentMetadata::of::<usize>());
+    }
+
     #[test]
     fn test_empty_compactable_storage_zero_sized_type() {
         let storage = CompactableStorage::<()>::default();
@@ -543,10 +854,10 @@ mod tests {
         storage.insert_entity_type(entity_type_index);
 
         unsafe {
-            let components = vec![1, 2, 3, 4, 5];
+            let mut components = vec![1, 2, 3, 4, 5];
             let ptr = components.as_ptr();
             storage.extend_memcopy(entity_type_index, ptr, 5);
-            mem::forget(components);
+            components.set_len(0);
         }
 
         storage.swap_remove(entity_type_index, ComponentIndex::new(4));
@@ -564,10 +875,10 @@ mod tests {
         storage.insert_entity_type(entity_type_index);
 
         unsafe {
-            let components = vec![1, 2, 3, 4, 5];
+            let mut components = vec![1, 2, 3, 4, 5];
             let ptr = components.as_ptr();
             storage.extend_memcopy(entity_type_index, ptr, 5);
-            mem::forget(components);
+            components.set_len(0);
         }
 
         storage.swap_remove(entity_type_index, ComponentIndex::new(0));
@@ -585,10 +896,10 @@ mod tests {
         storage.insert_entity_type(entity_type_index);
 
         unsafe {
-            let components = vec![1, 2, 3, 4, 5];
+            let mut components = vec![1, 2, 3, 4, 5];
             let ptr = components.as_ptr();
             storage.extend_memcopy(entity_type_index, ptr, 5);
-            mem::forget(components);
+            components.set_len(0);
         }
 
         storage.swap_remove(entity_type_index, ComponentIndex::new(2));
@@ -608,13 +919,256 @@ mod tests {
         storage.insert_entity_type(EntityTypeIndex::new(2));
 
         unsafe {
-            let components = vec![1, 2, 3, 4, 5];
+            let mut components = vec![1, 2, 3, 4, 5];
             let ptr = components.as_ptr();
             storage.extend_memcopy(entity_type_index, ptr, components.len());
-            mem::forget(components);
+            components.set_len(0);
         }
 
         assert_eq!(storage.len(), 3);
     }
-}
 
+    #[test]
+    fn test_shrink_to_fit_releases_capacity() {
+        let mut storage = CompactableStorage::<usize>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+
+        unsafe {
+            let mut components = vec![1, 2, 3, 4, 5];
+            let ptr = components.as_ptr();
+            storage.extend_memcopy(entity_type_index, ptr, components.len());
+            components.set_len(0);
+        }
+        storage.swap_remove(entity_type_index, ComponentIndex::new(0));
+        storage.swap_remove(entity_type_index, ComponentIndex::new(0));
+        storage.shrink_to_fit(entity_type_index);
+        let view = storage.get(entity_type_index).unwrap();
+        let expected = vec![4_usize, 2_usize, 3_usize];
+
+        assert_eq!(storage.capacity(entity_type_index), Some(3));
+        assert_eq!(view.into_slice(), &expected);
+    }
+
+    #[test]
+    fn test_shrink_to_fit_empty() {
+        let mut storage = CompactableStorage::<usize>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+
+        unsafe {
+            let mut components = vec![1, 2];
+            let ptr = components.as_ptr();
+            storage.extend_memcopy(entity_type_index, ptr, components.len());
+            components.set_len(0);
+        }
+        storage.swap_remove(entity_type_index, ComponentIndex::new(0));
+        storage.swap_remove(entity_type_index, ComponentIndex::new(0));
+        storage.shrink_to_fit(entity_type_index);
+
+        assert_eq!(storage.capacity(entity_type_index), Some(0));
+        assert!(storage.get(entity_type_index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_transfer_entity_type_releases_source() {
+        let mut src_storage = CompactableStorage::<usize>::default();
+        let mut dst_storage = CompactableStorage::<usize>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        src_storage.insert_entity_type(entity_type_index);
+        dst_storage.insert_entity_type(entity_type_index);
+
+        unsafe {
+            let mut src_components = vec![1, 2, 3];
+            src_storage.extend_memcopy(entity_type_index, src_components.as_ptr(), 3);
+            src_components.set_len(0);
+            let mut dst_components = vec![4, 5];
+            dst_storage.extend_memcopy(entity_type_index, dst_components.as_ptr(), 2);
+            dst_components.set_len(0);
+        }
+        src_storage.transfer_entity_type(entity_type_index, entity_type_index, &mut dst_storage);
+        let expected = vec![4_usize, 5_usize, 1_usize, 2_usize, 3_usize];
+
+        assert_eq!(src_storage.capacity(entity_type_index), Some(0));
+        assert!(src_storage.get(entity_type_index).unwrap().is_empty());
+        assert_eq!(dst_storage.get(entity_type_index).unwrap().into_slice(), &expected);
+    }
+
+    #[test]
+    fn test_remap_entity_types() {
+        let mut storage = CompactableStorage::<usize>::default();
+        for id in 0..3 {
+            storage.insert_entity_type(EntityTypeIndex::new(id));
+        }
+
+        unsafe {
+            let mut components = vec![1, 2, 3];
+            storage.extend_memcopy(EntityTypeIndex::new(2), components.as_ptr(), 3);
+            components.set_len(0);
+        }
+        let remap = [None, None, Some(EntityTypeIndex::new(0))];
+        storage.remap_entity_types(&remap);
+        let expected = vec![1_usize, 2_usize, 3_usize];
+
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get(EntityTypeIndex::new(0)).unwrap().into_slice(), &expected);
+        assert!(storage.get(EntityTypeIndex::new(2)).is_none());
+    }
+
+    #[test]
+    fn test_over_aligned_storage() {
+        let mut storage = CompactableStorage::<u8>::default();
+
+        assert!(storage.set_alignment(64));
+
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+        let empty_view = storage.get(entity_type_index).unwrap();
+
+        assert_eq!(empty_view.alignment(), 64);
+        assert_eq!(empty_view.into_slice().as_ptr() as usize % 64, 0);
+
+        unsafe {
+            let mut components = vec![1_u8, 2, 3];
+            storage.extend_memcopy(entity_type_index, components.as_ptr(), 3);
+            components.set_len(0);
+        }
+        let view = storage.get(entity_type_index).unwrap();
+
+        assert_eq!(view.alignment(), 64);
+        assert_eq!(view.into_slice().as_ptr() as usize % 64, 0);
+        assert!(!storage.set_alignment(32));
+    }
+
+    #[test]
+    fn test_iter_mut_views_do_not_alias() {
+        let mut storage = CompactableStorage::<usize>::default();
+        for id in 0..3 {
+            let entity_type_index = EntityTypeIndex::new(id);
+            storage.insert_entity_type(entity_type_index);
+            unsafe {
+                let mut components = vec![id; 4];
+                storage.extend_memcopy(entity_type_index, components.as_ptr(), components.len());
+                components.set_len(0);
+            }
+        }
+
+        // Hold every view at once and write through all of them.
+        let mut views: Vec<_> = storage.iter_mut().collect();
+        for (id, view) in views.iter_mut().enumerate() {
+            for component in view.iter_mut() {
+                *component += 10 * (id + 1);
+            }
+        }
+        drop(views);
+
+        for id in 0..3 {
+            let view = storage.get(EntityTypeIndex::new(id)).unwrap();
+
+            assert_eq!(view.into_slice(), &[id + 10 * (id + 1); 4]);
+        }
+    }
+
+    #[test]
+    fn test_reborrowed_view_writes_are_visible() {
+        let mut storage = CompactableStorage::<usize>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+        unsafe {
+            let mut components = vec![1, 2, 3];
+            storage.extend_memcopy(entity_type_index, components.as_ptr(), components.len());
+            components.set_len(0);
+        }
+
+        let mut view = storage.get_mut(entity_type_index).unwrap();
+        view.reborrow()[ComponentIndex::new(0)] = 4;
+        view[ComponentIndex::new(2)] = 6;
+
+        assert_eq!(storage.get(entity_type_index).unwrap().into_slice(), &[4, 2, 6]);
+    }
+
+    #[test]
+    fn test_storage_drops_components() {
+        let value = Arc::new(());
+        {
+            let mut storage = CompactableStorage::<Arc<()>>::default();
+            let entity_type_index = EntityTypeIndex::new(0);
+            storage.insert_entity_type(entity_type_index);
+            let mut components = vec![value.clone(), value.clone(), value.clone()];
+            unsafe {
+                storage.extend_memcopy(entity_type_index, components.as_ptr(), 3);
+                components.set_len(0);
+            }
+            storage.swap_remove(entity_type_index, ComponentIndex::new(1));
+
+            assert_eq!(Arc::strong_count(&value), 3);
+        }
+
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_clear_drops_components_and_keeps_capacity() {
+        let value = Arc::new(());
+        let mut storage = CompactableStorage::<Arc<()>>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+        let mut components = vec![value.clone(), value.clone(), value.clone()];
+        unsafe {
+            storage.extend_memcopy(entity_type_index, components.as_ptr(), 3);
+            components.set_len(0);
+        }
+        let capacity = storage.capacity(entity_type_index);
+        storage.clear();
+
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(storage.count(entity_type_index), Some(0));
+        assert_eq!(storage.capacity(entity_type_index), capacity);
+    }
+
+    #[test]
+    fn test_remove_marked_keeps_order_and_drops_removed() {
+        let value = Arc::new(());
+        let mut storage = CompactableStorage::<(usize, Arc<()>)>::default();
+        let entity_type_index = EntityTypeIndex::new(0);
+        storage.insert_entity_type(entity_type_index);
+        let mut components: Vec<_> = (0..5).map(|i| (i, value.clone())).collect();
+        unsafe {
+            storage.extend_memcopy(entity_type_index, components.as_ptr(), 5);
+            components.set_len(0);
+        }
+        storage.remove_marked(entity_type_index, &[true, false, true, false, false]);
+        let remaining: Vec<usize> = storage.get(entity_type_index).unwrap().iter().map(|(i, _)| *i).collect();
+
+        assert_eq!(remaining, vec![1, 3, 4]);
+        assert_eq!(Arc::strong_count(&value), 4);
+        assert_eq!(storage.count(entity_type_index), Some(3));
+    }
+
+    #[test]
+    fn test_transfer_entity_type_moves_components_once() {
+        let value = Arc::new(());
+        {
+            let mut src_storage = CompactableStorage::<Arc<()>>::default();
+            let mut dst_storage = CompactableStorage::<Arc<()>>::default();
+            let entity_type_index = EntityTypeIndex::new(0);
+            src_storage.insert_entity_type(entity_type_index);
+            dst_storage.insert_entity_type(entity_type_index);
+            let mut components = vec![value.clone(), value.clone(), value.clone()];
+            unsafe {
+                src_storage.extend_memcopy(entity_type_index, components.as_ptr(), 2);
+                dst_storage.extend_memcopy(entity_type_index, components.as_ptr().add(2), 1);
+                components.set_len(0);
+            }
+            // The destination holds a value, so the components are copied rather
+            // than the arrays swapped.
+            src_storage.transfer_entity_type(entity_type_index, entity_type_index, &mut dst_storage);
+            drop(src_storage);
+
+            assert_eq!(Arc::strong_count(&value), 4);
+            assert_eq!(dst_storage.get(entity_type_index).unwrap().len(), 3);
+        }
+
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -1,7 +1,15 @@
 use std::collections::{
+    HashSet,
     VecDeque,
 };
 use std::fmt;
+use std::sync::{
+    Arc,
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
 
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -21,17 +29,90 @@ impl fmt::Display for Entity {
     }
 }
 
+/// A thread-safe source of entity ids shared by several entity allocators. 
+/// Each allocator takes ids from the source one block at a time, so the 
+/// allocators never hand out the same id.
 #[derive(Debug)]
+pub struct EntityIdSource {
+    next_id: AtomicU64,
+    block_size: u64,
+}
+
+impl EntityIdSource {
+    pub fn new(block_size: u64) -> EntityIdSource {
+        assert!(block_size > 0);
+        EntityIdSource {
+            next_id: AtomicU64::new(0),
+            block_size,
+        }
+    }
+
+    /// Take the next block of ids from the source, returning its start and end.
+    fn take_block(&self) -> (u64, u64) {
+        let start = self.next_id.fetch_add(self.block_size, Ordering::Relaxed);
+
+        (start, start + self.block_size)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct EntityAllocator {
     max_id: u64,
+    end_id: u64,
     available_entities: VecDeque<Entity>,
+    /// Reserved ids the allocator has not reached yet, skipped once it does.
+    reserved_entities: HashSet<Entity>,
+    source: Option<Arc<EntityIdSource>>,
 }
 
 impl EntityAllocator {
     pub fn new() -> EntityAllocator {
         EntityAllocator {
             max_id: 0,
+            end_id: u64::MAX,
+            available_entities: VecDeque::new(),
+            reserved_entities: HashSet::new(),
+            source: None,
+        }
+    }
+
+    /// Construct an allocator that takes its ids from a shared id source. The 
+    /// allocator starts with an empty block, so it takes its first block when 
+    /// it allocates its first entity.
+    pub fn with_source(source: Arc<EntityIdSource>) -> EntityAllocator {
+        EntityAllocator {
+            max_id: 0,
+            end_id: 0,
             available_entities: VecDeque::new(),
+            reserved_entities: HashSet::new(),
+            source: Some(source),
+        }
+    }
+
+    /// Construct an allocator for a copy of the entities of this allocator. A
+    /// shared allocator starts over with a fresh block from its id source, so
+    /// the two allocators never hand out the same id. A standalone allocator is
+    /// copied, so both continue with the same ids, like any two standalone
+    /// allocators.
+    pub fn fork(&self) -> EntityAllocator {
+        match &self.source {
+            Some(source) => EntityAllocator::with_source(source.clone()),
+            None => self.clone(),
+        }
+    }
+
+    /// Determine whether the allocator takes its ids from a shared id source.
+    pub fn is_shared(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Determine whether the two allocators allocate ids in the same id space, 
+    /// i.e. they are both standalone or share the same id source.
+    pub fn shares_ids_with(&self, other: &EntityAllocator) -> bool {
+        match (&self.source, &other.source) {
+            (Some(source), Some(other_source)) => Arc::ptr_eq(source, other_source),
+            (None, None) => true,
+            _ => false,
         }
     }
 
@@ -39,19 +120,50 @@ impl EntityAllocator {
         if !self.available_entities.is_empty() {
             self.available_entities.pop_front().unwrap()
         } else {
-            let new_entity = Entity(self.max_id);
-            self.max_id += 1;
-
-            new_entity
+            loop {
+                if self.max_id == self.end_id {
+                    let source = self.source.as_ref().expect("Ran out of entity ids");
+                    let (start, end) = source.take_block();
+                    self.max_id = start;
+                    self.end_id = end;
+                }
+
+                let new_entity = Entity(self.max_id);
+                self.max_id += 1;
+
+                if !self.reserved_entities.remove(&new_entity) {
+                    return new_entity;
+                }
+            }
         }
     }
 
     pub fn deallocate(&mut self, entity: Entity) {
-        if entity.id() < self.max_id {
-            // The entity has been previously allocated.
+        if self.is_shared() || entity.id() < self.max_id {
+            // The entity has been previously allocated. Entities from a shared id 
+            // source belong to whichever allocator deallocates them.
             self.available_entities.push_back(entity)
         }
     }
+
+    /// Mark entities that were allocated elsewhere as allocated, so that they 
+    /// are never handed out by this allocator. The entities must not currently 
+    /// be allocated by this allocator.
+    pub fn reserve<I: IntoIterator<Item = Entity>>(&mut self, entities: I) {
+        let mut reserved = HashSet::new();
+        for entity in entities {
+            if !self.is_shared() && entity.id() >= self.max_id {
+                // The allocator has not reached the id yet, so it skips the id
+                // when it gets there.
+                self.reserved_entities.insert(entity);
+            } else {
+                reserved.insert(entity);
+            }
+        }
+        if !reserved.is_empty() {
+            self.available_entities.retain(|entity| !reserved.contains(entity));
+        }
+    }
 }
 
 impl Default for EntityAllocator {
@@ -89,5 +201,50 @@ mod tests {
 
         assert_eq!(result, expected);
     }
-}
 
+    #[test]
+    fn test_reserve_skips_reserved_entities() {
+        let mut allocator = EntityAllocator::new();
+        allocator.reserve(vec![Entity(1), Entity(3)]);
+        let result: Vec<_> = allocator.by_ref().take(3).collect();
+        let expected = vec![Entity(0), Entity(2), Entity(4)];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_reserve_far_ahead_keeps_allocating_from_the_start() {
+        let mut allocator = EntityAllocator::new();
+        allocator.reserve(vec![Entity(1 << 40), Entity(1)]);
+        let result: Vec<_> = allocator.by_ref().take(2).collect();
+
+        assert_eq!(result, vec![Entity(0), Entity(2)]);
+        assert!(allocator.available_entities.is_empty());
+        assert_eq!(allocator.reserved_entities.len(), 1);
+    }
+
+    #[test]
+    fn test_fork_shared_allocator_takes_a_new_block() {
+        let mut allocator = EntityAllocator::with_source(Arc::new(EntityIdSource::new(4)));
+        allocator.allocate();
+        let mut fork = allocator.fork();
+
+        assert_eq!(allocator.allocate(), Entity(1));
+        assert_eq!(fork.allocate(), Entity(4));
+        assert!(fork.shares_ids_with(&allocator));
+    }
+
+    #[test]
+    fn test_shared_allocators_allocate_disjoint_blocks() {
+        let source = Arc::new(EntityIdSource::new(2));
+        let mut allocator0 = EntityAllocator::with_source(source.clone());
+        let mut allocator1 = EntityAllocator::with_source(source);
+        let entities0: Vec<_> = allocator0.by_ref().take(3).collect();
+        let entities1: Vec<_> = allocator1.by_ref().take(2).collect();
+
+        assert_eq!(entities0, vec![Entity(0), Entity(1), Entity(2)]);
+        assert_eq!(entities1, vec![Entity(4), Entity(5)]);
+        assert!(allocator0.shares_ids_with(&allocator1));
+        assert!(!allocator0.shares_ids_with(&EntityAllocator::new()));
+    }
+}
@@ -4,12 +4,12 @@ use crate::component::{
 use crate::storage::{
     EntityType,
     EntityLocation,
-    ComponentStorage,
     StoreComponentsIn,
 };
 use crate::world::{
     World,
 };
+use std::mem;
 
 
 pub struct Entry<'a> {
@@ -21,7 +21,7 @@ impl<'a> Entry<'a> {
     pub(crate) fn new(location: EntityLocation, world: &'a mut World) -> Self {
         Self {
             location,
-            world: world,
+            world,
         }
     }
 
@@ -33,20 +33,64 @@ impl<'a> Entry<'a> {
         self.location
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn get_component<T>(&self) -> Result<&T, ()> 
+    where
+        T: Component + StoreComponentsIn,
+    {
+        let component = self.location.component();
+        let entity_type = self.location.entity_type();
+        // SAFETY: The entry borrows the world exclusively, so no storage of the 
+        // world can be borrowed mutably.
+        unsafe {
+            self.world
+                .components()
+                .get_component_unchecked::<T>(entity_type, component)
+                .ok_or(())
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn get_component_mut<T>(&mut self) -> Result<&mut T, ()> 
     where
         T: Component + StoreComponentsIn,
     {
         let component = self.location.component();
         let entity_type = self.location.entity_type();
         self.world
-            .components()
-            .get_view::<T>()
-            .and_then(move |storage| storage.get(entity_type))
-            .and_then(move |view| view.into_slice().get(component.id()))
-            .ok_or_else(|| {
+            .components_mut()
+            .get_component_mut::<T>(entity_type, component)
+            .ok_or(())
+    }
+
+    /// Remove the component of type `T` from the entity and return it. The rest
+    /// of the entity's components move to the entity type without `T`.
+    pub fn take_component<T>(&mut self) -> Option<T>
+    where
+        T: Component + StoreComponentsIn,
+    {
+        let (component, location) = self.world.take_component_at::<T>(self.location)?;
+        self.location = location;
 
-            })
+        Some(component)
+    }
+
+    /// Replace the component of type `T` of the entity, returning the old value.
+    /// If the entity has no component of type `T`, it is left unchanged and 
+    /// `value` is dropped.
+    pub fn replace_component<T>(&mut self, value: T) -> Option<T>
+    where
+        T: Component + StoreComponentsIn,
+    {
+        self.get_component_mut::<T>()
+            .ok()
+            .map(|component| mem::replace(component, value))
     }
-}
 
+    pub fn has_component<T>(&self) -> bool 
+    where
+        T: Component + StoreComponentsIn,
+    {
+        self.entity_type().contains_component::<T>()
+    }
+}
@@ -0,0 +1,216 @@
+use crate::entity::{
+    Entity,
+};
+use crate::registry::{
+    MapEntities,
+};
+use crate::world::{
+    World,
+};
+use std::collections::{
+    HashMap,
+};
+
+
+/// The parent of an entity in the hierarchy.
+///
+/// Hierarchy components are sparse set components, so attaching an entity to a
+/// parent does not move its other components. They are maintained by
+/// [`World::set_parent`] and friends, and should not be inserted or removed by hand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Parent(Entity);
+
+impl Parent {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        if let Some(entity) = entity_map.get(&self.0) {
+            self.0 = *entity;
+        }
+    }
+}
+
+/// The children of an entity in the hierarchy, in the order they were attached.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Children(Vec<Entity>);
+
+impl Children {
+    pub fn as_slice(&self) -> &[Entity] {
+        &self.0
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.0.contains(&entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        self.0.retain(|child| *child != entity);
+    }
+}
+
+impl MapEntities for Children {
+    fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for child in self.0.iter_mut() {
+            if let Some(entity) = entity_map.get(child) {
+                *child = *entity;
+            }
+        }
+    }
+}
+
+impl World {
+    /// Attach `child` to `parent`, detaching it from its previous parent. Returns
+    /// `false` if either entity does not exist, or if `parent` is `child` itself
+    /// or one of its descendants, in which case the hierarchy is unchanged.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> bool {
+        if !self.contains(child) || !self.contains(parent) {
+            return false;
+        }
+        if self.ancestors(parent).chain(Some(parent)).any(|ancestor| ancestor == child) {
+            return false;
+        }
+
+        self.remove_parent(child);
+        self.insert_sparse(child, Parent(parent));
+        match self.get_sparse_mut::<Children>(parent) {
+            Some(children) => children.0.push(child),
+            None => {
+                self.insert_sparse(parent, Children(vec![child]));
+            }
+        }
+
+        true
+    }
+
+    /// Detach an entity from its parent, returning the parent if it had one.
+    pub fn remove_parent(&mut self, child: Entity) -> Option<Entity> {
+        let parent = self.remove_sparse::<Parent>(child)?.get();
+        self.remove_child_entry(parent, child);
+
+        Some(parent)
+    }
+
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.get_sparse::<Parent>(entity).map(|parent| parent.get())
+    }
+
+    /// The children of an entity, which is empty if the entity has no children.
+    pub fn children(&self, entity: Entity) -> &[Entity] {
+        self.get_sparse::<Children>(entity)
+            .map(|children| children.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Iterate over the parent of an entity, its parent's parent, and so on up
+    /// to the root of its hierarchy.
+    pub fn ancestors(&self, entity: Entity) -> impl Iterator<Item = Entity> + '_ {
+        let mut current = entity;
+        std::iter::from_fn(move || {
+            current = self.parent(current)?;
+            Some(current)
+        })
+    }
+
+    /// Collect the descendants of an entity in depth first order, children
+    /// before grandchildren of the same branch.
+    pub fn descendants(&self, entity: Entity) -> Vec<Entity> {
+        let mut descendants = Vec::new();
+        let mut stack: Vec<Entity> = self.children(entity).iter().rev().copied().collect();
+        while let Some(descendant) = stack.pop() {
+            descendants.push(descendant);
+            stack.extend(self.children(descendant).iter().rev());
+        }
+
+        descendants
+    }
+
+    /// Remove an entity along with all of its descendants. Returns the number of
+    /// entities removed, not counting those already removed as the sources of
+    /// relations to an entity removed before them.
+    pub fn remove_recursive(&mut self, entity: Entity) -> usize {
+        let descendants = self.descendants(entity);
+        let mut removed = usize::from(self.remove(entity));
+        for descendant in descendants {
+            removed += usize::from(self.remove(descendant));
+        }
+
+        removed
+    }
+
+    /// Unlink an entity that is being removed from the hierarchy: it leaves the
+    /// children of its parent, and its children lose their parent.
+    pub(crate) fn detach_from_hierarchy(&mut self, entity: Entity) {
+        self.remove_parent(entity);
+        if let Some(children) = self.remove_sparse::<Children>(entity) {
+            for child in children.0 {
+                self.remove_sparse::<Parent>(child);
+            }
+        }
+    }
+
+    fn remove_child_entry(&mut self, parent: Entity, child: Entity) {
+        let is_empty = match self.get_sparse_mut::<Children>(parent) {
+            Some(children) => {
+                children.remove(child);
+                children.is_empty()
+            }
+            None => false,
+        };
+        if is_empty {
+            self.remove_sparse::<Children>(parent);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::world::{
+        World,
+    };
+
+
+    fn spawn(world: &mut World) -> crate::entity::Entity {
+        world.push((0_u32, 0_u64))
+    }
+
+    #[test]
+    fn test_set_parent_rejects_cycles() {
+        let mut world = World::new();
+        let root = spawn(&mut world);
+        let child = spawn(&mut world);
+        let grandchild = spawn(&mut world);
+
+        assert!(world.set_parent(child, root));
+        assert!(world.set_parent(grandchild, child));
+        assert!(!world.set_parent(root, grandchild));
+        assert!(!world.set_parent(root, root));
+        assert_eq!(world.parent(root), None);
+    }
+
+    #[test]
+    fn test_set_parent_moves_child() {
+        let mut world = World::new();
+        let parent0 = spawn(&mut world);
+        let parent1 = spawn(&mut world);
+        let child = spawn(&mut world);
+        world.set_parent(child, parent0);
+        world.set_parent(child, parent1);
+
+        assert_eq!(world.children(parent0), &[]);
+        assert_eq!(world.children(parent1), &[child]);
+        assert_eq!(world.parent(child), Some(parent1));
+    }
+}
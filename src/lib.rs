@@ -1,14 +1,41 @@
 extern crate downcast_rs as downcast;
 
 
+mod allocator;
+mod cell;
 mod compactable;
+mod chunked;
 mod entity;
 mod entry;
+mod hierarchy;
 mod component;
 mod storage;
+mod prefab;
+mod query;
+mod registry;
+mod relation;
+mod sparse;
+mod stats;
+mod universe;
 
 pub mod world;
+#[cfg(feature = "transform")]
+pub mod transform;
 
 
 pub use world::*;
+pub use allocator::*;
+pub use cell::*;
+pub use component::*;
+pub use entity::*;
+pub use chunked::*;
+pub use entry::*;
+pub use hierarchy::*;
+pub use prefab::*;
+pub use query::*;
+pub use registry::*;
+pub use relation::*;
+pub use sparse::*;
+pub use stats::*;
+pub use universe::*;
 
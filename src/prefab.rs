@@ -0,0 +1,280 @@
+use crate::component::{
+    Component,
+    ComponentTypeIndex,
+};
+use crate::registry::{
+    extend_clones,
+};
+#[cfg(feature = "serialize")]
+use crate::registry::{
+    ComponentRegistry,
+    DeserializeFn,
+};
+use crate::storage::{
+    EntityLayout,
+    EntityTypeIndex,
+    OpaqueComponentStorage,
+    StoreComponentsIn,
+};
+#[cfg(feature = "serialize")]
+use serde::de::{
+    self,
+    DeserializeSeed,
+    Deserializer,
+    MapAccess,
+    SeqAccess,
+    Visitor,
+};
+#[cfg(feature = "serialize")]
+use std::fmt;
+
+
+/// A type erased component value of a prefab.
+pub(crate) trait PrefabComponent: Send + Sync {
+    fn component_type(&self) -> ComponentTypeIndex;
+
+    /// Append `count` clones of the component to an entity type in a storage of
+    /// the same component type.
+    fn clone_components(&self, storage: &mut dyn OpaqueComponentStorage, entity_type: EntityTypeIndex, count: usize);
+}
+
+struct PrefabValue<T> {
+    value: T,
+}
+
+impl<T> PrefabComponent for PrefabValue<T>
+where
+    T: Component + Clone,
+{
+    fn component_type(&self) -> ComponentTypeIndex {
+        ComponentTypeIndex::of::<T>()
+    }
+
+    fn clone_components(&self, storage: &mut dyn OpaqueComponentStorage, entity_type: EntityTypeIndex, count: usize) {
+        let clones = vec![self.value.clone(); count];
+        extend_clones(storage, entity_type, clones);
+    }
+}
+
+/// A template for entities: a set of component values, along with the prefabs 
+/// of child entities, that a world instantiates with [`World::spawn_prefab`].
+///
+/// [`World::spawn_prefab`]: crate::World::spawn_prefab
+#[derive(Default)]
+pub struct Prefab {
+    layout: EntityLayout,
+    components: Vec<Box<dyn PrefabComponent>>,
+    children: Vec<Prefab>,
+}
+
+impl Prefab {
+    pub fn new() -> Prefab {
+        Prefab::default()
+    }
+
+    /// Add a component to the prefab. If the prefab already has a component of
+    /// type `T`, it is replaced.
+    pub fn with<T: Component + Clone + StoreComponentsIn>(mut self, component: T) -> Prefab {
+        self.insert(component);
+        self
+    }
+
+    /// Add a child prefab, spawned as a child of the prefab's own entity.
+    pub fn with_child(mut self, child: Prefab) -> Prefab {
+        self.children.push(child);
+        self
+    }
+
+    /// Add a component to the prefab, replacing the prefab's component of type 
+    /// `T` if it has one.
+    pub fn insert<T: Component + Clone + StoreComponentsIn>(&mut self, component: T) {
+        let value = Box::new(PrefabValue {
+            value: component,
+        });
+        let component_type = ComponentTypeIndex::of::<T>();
+        let existing = self.components
+            .iter()
+            .position(|component| component.component_type() == component_type);
+        match existing {
+            Some(index) => {
+                self.components[index] = value;
+            }
+            None => {
+                self.layout.register_component::<T>();
+                self.components.push(value);
+            }
+        }
+    }
+
+    pub fn contains_component<T: Component>(&self) -> bool {
+        self.layout.contains_component::<T>()
+    }
+
+    /// Load a prefab from data, such as a designer authored file. The prefab
+    /// is a map with an optional `components` map, from the names registered
+    /// with [`ComponentRegistry::register_deserialize`] to component values, and
+    /// an optional `children` list of child prefabs in the same format:
+    ///
+    /// ```json
+    /// { "components": { "position": [0, 1, 0] }, "children": [{ "components": {} }] }
+    /// ```
+    #[cfg(feature = "serialize")]
+    pub fn load<'de, D: Deserializer<'de>>(registry: &ComponentRegistry, deserializer: D) -> Result<Prefab, D::Error> {
+        PrefabLoader::new(registry).deserialize(deserializer)
+    }
+
+    pub fn children(&self) -> &[Prefab] {
+        &self.children
+    }
+
+    pub(crate) fn layout(&self) -> &EntityLayout {
+        &self.layout
+    }
+
+    pub(crate) fn components(&self) -> &[Box<dyn PrefabComponent>] {
+        &self.components
+    }
+}
+
+/// Deserializes a [`Prefab`], looking up its component types in a registry.
+/// See [`Prefab::load`].
+#[cfg(feature = "serialize")]
+#[derive(Copy, Clone)]
+pub struct PrefabLoader<'a> {
+    registry: &'a ComponentRegistry,
+}
+
+#[cfg(feature = "serialize")]
+impl<'a> PrefabLoader<'a> {
+    pub fn new(registry: &'a ComponentRegistry) -> Self {
+        Self {
+            registry,
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> DeserializeSeed<'de> for PrefabLoader<'a> {
+    type Value = Prefab;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Prefab, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> Visitor<'de> for PrefabLoader<'a> {
+    type Value = Prefab;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a prefab")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Prefab, A::Error> {
+        let mut prefab = Prefab::new();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "components" => map.next_value_seed(ComponentsLoader {
+                    registry: self.registry,
+                    prefab: &mut prefab,
+                })?,
+                "children" => map.next_value_seed(ChildrenLoader {
+                    loader: self,
+                    prefab: &mut prefab,
+                })?,
+                _ => return Err(de::Error::unknown_field(&key, &["components", "children"])),
+            }
+        }
+
+        Ok(prefab)
+    }
+}
+
+/// Deserializes the components of a prefab into the prefab.
+#[cfg(feature = "serialize")]
+struct ComponentsLoader<'a> {
+    registry: &'a ComponentRegistry,
+    prefab: &'a mut Prefab,
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> DeserializeSeed<'de> for ComponentsLoader<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> Visitor<'de> for ComponentsLoader<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map from component names to components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(name) = map.next_key::<String>()? {
+            let deserialize = self.registry
+                .deserialize_fn(&name)
+                .ok_or_else(|| de::Error::custom(format!("Unknown component {}", name)))?;
+            map.next_value_seed(ComponentLoader {
+                deserialize,
+                prefab: &mut *self.prefab,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes a single component into a prefab.
+#[cfg(feature = "serialize")]
+struct ComponentLoader<'a> {
+    deserialize: DeserializeFn,
+    prefab: &'a mut Prefab,
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> DeserializeSeed<'de> for ComponentLoader<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.deserialize)(self.prefab, &mut deserializer).map_err(de::Error::custom)
+    }
+}
+
+/// Deserializes the child prefabs of a prefab into the prefab.
+#[cfg(feature = "serialize")]
+struct ChildrenLoader<'a> {
+    loader: PrefabLoader<'a>,
+    prefab: &'a mut Prefab,
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> DeserializeSeed<'de> for ChildrenLoader<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, 'de> Visitor<'de> for ChildrenLoader<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of prefabs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(child) = seq.next_element_seed(self.loader)? {
+            self.prefab.children.push(child);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,893 @@
+use crate::cell::{
+    BorrowError,
+    StorageRef,
+    StorageRefMut,
+};
+use crate::component::{
+    Component,
+};
+use crate::entity::{
+    Entity,
+};
+use crate::sparse::{
+    SparseSetStorage,
+};
+use crate::storage::{
+    ComponentIndex,
+    EntityLocation,
+    EntityType,
+    EntityTypeIndex,
+    OpaqueComponentStorage,
+    StoreComponentsIn,
+};
+use crate::world::{
+    World,
+};
+use std::borrow::{
+    Cow,
+};
+use std::cmp;
+use std::marker::{
+    PhantomData,
+};
+use std::mem;
+use std::ops::{
+    Range,
+};
+use std::slice;
+
+
+/// The number of entities a parallel query hands to a thread at a time.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_BATCH_SIZE: usize = 1024;
+
+/// Shared access to the components of type `T` in a query.
+pub struct Read<T> {
+    _marker: PhantomData<T>,
+}
+
+/// Mutable access to the components of type `T` in a query.
+pub struct Write<T> {
+    _marker: PhantomData<T>,
+}
+
+/// Shared access to the sparse set components of type `T` in a query. Sparse
+/// set components live outside of entity types, so the view matches every
+/// entity and fetches `None` for the entities without the component.
+pub struct Sparse<T> {
+    _marker: PhantomData<T>,
+}
+
+/// Filters a query down to the sources of the relations of type `R` targeting
+/// an entity. The filter walks the sources the relation storage keeps for the
+/// target, so it visits no other entity of the matching entity types.
+pub struct RelatedTo<R> {
+    target: Entity,
+    _marker: PhantomData<R>,
+}
+
+impl<R> RelatedTo<R>
+where
+    R: Component + Clone,
+{
+    pub fn new(target: Entity) -> Self {
+        Self {
+            target,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn target(&self) -> Entity {
+        self.target
+    }
+
+    /// The sources of the relations targeting the target of the filter.
+    fn sources<'w>(&self, world: &'w World) -> &'w [Entity] {
+        world.relation_sources::<R>(self.target)
+    }
+}
+
+/// A range of components of an entity type.
+pub type Run = (EntityTypeIndex, Range<usize>);
+
+/// The components a query fetches from each entity, and how. Implemented for
+/// [`Read`], [`Write`], [`Sparse`] and tuples of them.
+///
+/// A view reaches its storages through `OpaqueComponentStorage`, so it works
+/// with whatever storage a component type is registered with. The components
+/// are fetched in runs that every storage of the view holds contiguously: a
+/// whole entity type for the default storage, or a single chunk of an entity
+/// type for a [`ChunkedStorage`](crate::ChunkedStorage).
+pub trait View {
+    /// The storages borrowed from the world while the query runs.
+    type Storages<'w>;
+    /// The components of a run of entities of the same entity type.
+    type Chunk<'a>: Send;
+    /// The components of a single entity.
+    type Item<'a>;
+
+    /// Determine whether the entities of an entity type have every component
+    /// the view fetches.
+    fn matches(entity_type: &EntityType) -> bool;
+
+    fn borrow(world: &World) -> Result<Self::Storages<'_>, BorrowError>;
+
+    /// Return the number of components of an entity type, starting at `index`,
+    /// that every storage of the view holds contiguously.
+    fn run_len(storages: &Self::Storages<'_>, entity_type: EntityTypeIndex, index: usize) -> usize;
+
+    /// Get the chunk of every run, in the order of `runs`. The runs must not
+    /// overlap, and each must be contiguous in every storage of the view.
+    fn chunks<'a>(storages: &'a mut Self::Storages<'_>, runs: &[Run]) -> Vec<Self::Chunk<'a>>;
+
+    /// Like [`View::chunks`], reaching the storages without their borrow flags.
+    ///
+    /// # Safety
+    ///
+    /// The view must not conflict with itself, and none of its storages may be
+    /// borrowed elsewhere while the chunks are alive.
+    unsafe fn chunks_unchecked<'a>(world: &'a World, runs: &[Run]) -> Vec<Self::Chunk<'a>>;
+
+    fn chunk_len(chunk: &Self::Chunk<'_>) -> usize;
+
+    /// Split a chunk in two at `mid`.
+    fn split_chunk(chunk: Self::Chunk<'_>, mid: usize) -> (Self::Chunk<'_>, Self::Chunk<'_>);
+
+    /// Take the components of the first entity off the front of a chunk.
+    fn next_item<'a>(chunk: &mut Self::Chunk<'a>) -> Option<Self::Item<'a>>;
+}
+
+/// A view that only reads components, so its items can be fetched through a
+/// shared borrow of the world.
+///
+/// # Safety
+///
+/// [`View::chunks_unchecked`] must only create shared references, and the
+/// storages of [`View::borrow`] must keep the components from being borrowed
+/// mutably while they are alive.
+pub unsafe trait ReadOnlyView: View {}
+
+/// The components of type `T` in a run.
+///
+/// # Safety
+///
+/// `storage` must be the storage of `T`, and the run must be contiguous in it.
+unsafe fn run_slice<'a, T>(storage: &'a dyn OpaqueComponentStorage, run: &Run) -> &'a [T] {
+    let (entity_type, range) = run;
+    match storage.get_component_ptr(*entity_type, ComponentIndex::new(range.start)) {
+        Some(ptr) if !range.is_empty() => slice::from_raw_parts(ptr as *const T, range.len()),
+        _ => &[],
+    }
+}
+
+/// The components of type `T` in a run, mutably.
+///
+/// # Safety
+///
+/// `storage` must be the storage of `T`, the run must be contiguous in it, and
+/// no other reference to the components of the run may be alive for `'a`.
+unsafe fn run_slice_mut<'a, T>(storage: &mut dyn OpaqueComponentStorage, run: &Run) -> &'a mut [T] {
+    let (entity_type, range) = run;
+    match storage.get_component_ptr_mut(*entity_type, ComponentIndex::new(range.start)) {
+        Some(ptr) if !range.is_empty() => slice::from_raw_parts_mut(ptr as *mut T, range.len()),
+        _ => &mut [],
+    }
+}
+
+impl<T> View for Read<T>
+where
+    T: Component + StoreComponentsIn,
+{
+    type Storages<'w> = StorageRef<'w, dyn OpaqueComponentStorage>;
+    type Chunk<'a> = &'a [T];
+    type Item<'a> = &'a T;
+
+    fn matches(entity_type: &EntityType) -> bool {
+        entity_type.contains_component::<T>()
+    }
+
+    fn borrow(world: &World) -> Result<Self::Storages<'_>, BorrowError> {
+        world.components().try_borrow_opaque::<T>()
+    }
+
+    fn run_len(storages: &Self::Storages<'_>, entity_type: EntityTypeIndex, index: usize) -> usize {
+        storages.run_len(entity_type, ComponentIndex::new(index))
+    }
+
+    fn chunks<'a>(storages: &'a mut Self::Storages<'_>, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        let storage: &'a dyn OpaqueComponentStorage = &**storages;
+        // SAFETY: The storage of `T` only holds values of type `T`.
+        runs.iter().map(|run| unsafe { run_slice(storage, run) }).collect()
+    }
+
+    unsafe fn chunks_unchecked<'a>(world: &'a World, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        match world.components().get_opaque_unchecked::<T>() {
+            Some(storage) => runs.iter().map(|run| run_slice(storage, run)).collect(),
+            None => runs.iter().map(|_| &[][..]).collect(),
+        }
+    }
+
+    fn chunk_len(chunk: &Self::Chunk<'_>) -> usize {
+        chunk.len()
+    }
+
+    fn split_chunk(chunk: Self::Chunk<'_>, mid: usize) -> (Self::Chunk<'_>, Self::Chunk<'_>) {
+        chunk.split_at(mid)
+    }
+
+    fn next_item<'a>(chunk: &mut Self::Chunk<'a>) -> Option<Self::Item<'a>> {
+        let (first, rest) = chunk.split_first()?;
+        *chunk = rest;
+
+        Some(first)
+    }
+}
+
+unsafe impl<T> ReadOnlyView for Read<T>
+where
+    T: Component + StoreComponentsIn,
+{}
+
+impl<T> View for Write<T>
+where
+    T: Component + StoreComponentsIn,
+{
+    type Storages<'w> = StorageRefMut<'w, dyn OpaqueComponentStorage>;
+    type Chunk<'a> = &'a mut [T];
+    type Item<'a> = &'a mut T;
+
+    fn matches(entity_type: &EntityType) -> bool {
+        entity_type.contains_component::<T>()
+    }
+
+    fn borrow(world: &World) -> Result<Self::Storages<'_>, BorrowError> {
+        world.components().try_borrow_opaque_mut::<T>()
+    }
+
+    fn run_len(storages: &Self::Storages<'_>, entity_type: EntityTypeIndex, index: usize) -> usize {
+        storages.run_len(entity_type, ComponentIndex::new(index))
+    }
+
+    fn chunks<'a>(storages: &'a mut Self::Storages<'_>, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        let storage: &'a mut dyn OpaqueComponentStorage = &mut **storages;
+        // SAFETY: The storage of `T` only holds values of type `T`, the storage
+        // is borrowed mutably for `'a`, and the runs do not overlap.
+        runs.iter().map(|run| unsafe { run_slice_mut(&mut *storage, run) }).collect()
+    }
+
+    unsafe fn chunks_unchecked<'a>(world: &'a World, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        match world.components().get_opaque_unchecked_mut::<T>() {
+            Some(storage) => runs.iter().map(|run| run_slice_mut(&mut *storage, run)).collect(),
+            None => runs.iter().map(|_| &mut [][..]).collect(),
+        }
+    }
+
+    fn chunk_len(chunk: &Self::Chunk<'_>) -> usize {
+        chunk.len()
+    }
+
+    fn split_chunk(chunk: Self::Chunk<'_>, mid: usize) -> (Self::Chunk<'_>, Self::Chunk<'_>) {
+        chunk.split_at_mut(mid)
+    }
+
+    fn next_item<'a>(chunk: &mut Self::Chunk<'a>) -> Option<Self::Item<'a>> {
+        let (first, rest) = mem::take(chunk).split_first_mut()?;
+        *chunk = rest;
+
+        Some(first)
+    }
+}
+
+impl<T> View for Sparse<T>
+where
+    T: Component,
+{
+    type Storages<'w> = &'w World;
+    type Chunk<'a> = (&'a [Entity], Option<&'a SparseSetStorage<T>>);
+    type Item<'a> = Option<&'a T>;
+
+    fn matches(_entity_type: &EntityType) -> bool {
+        true
+    }
+
+    fn borrow(world: &World) -> Result<Self::Storages<'_>, BorrowError> {
+        // Sparse set components only change through an exclusive borrow of the
+        // world, so they need no borrow flags.
+        Ok(world)
+    }
+
+    fn run_len(_storages: &Self::Storages<'_>, _entity_type: EntityTypeIndex, _index: usize) -> usize {
+        usize::MAX
+    }
+
+    fn chunks<'a>(storages: &'a mut Self::Storages<'_>, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        // SAFETY: Sparse set components are only read.
+        unsafe { Self::chunks_unchecked(storages, runs) }
+    }
+
+    unsafe fn chunks_unchecked<'a>(world: &'a World, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+        let storage = world.sparse_components().get::<T>();
+        runs
+            .iter()
+            .map(|(entity_type, range)| (&world.entity_types()[entity_type.id()].entities()[range.clone()], storage))
+            .collect()
+    }
+
+    fn chunk_len(chunk: &Self::Chunk<'_>) -> usize {
+        chunk.0.len()
+    }
+
+    fn split_chunk(chunk: Self::Chunk<'_>, mid: usize) -> (Self::Chunk<'_>, Self::Chunk<'_>) {
+        let (entities, storage) = chunk;
+        let (left, right) = entities.split_at(mid);
+
+        ((left, storage), (right, storage))
+    }
+
+    fn next_item<'a>(chunk: &mut Self::Chunk<'a>) -> Option<Self::Item<'a>> {
+        let (first, rest) = chunk.0.split_first()?;
+        chunk.0 = rest;
+
+        Some(chunk.1.and_then(|storage| storage.get(*first)))
+    }
+}
+
+unsafe impl<T> ReadOnlyView for Sparse<T>
+where
+    T: Component,
+{}
+
+macro_rules! impl_view_for_tuple {
+    ($first:ident $first_index:tt $(, $name:ident $index:tt)*) => {
+        impl<$first: View $(, $name: View)*> View for ($first, $($name,)*) {
+            type Storages<'w> = ($first::Storages<'w>, $($name::Storages<'w>,)*);
+            type Chunk<'a> = ($first::Chunk<'a>, $($name::Chunk<'a>,)*);
+            type Item<'a> = ($first::Item<'a>, $($name::Item<'a>,)*);
+
+            fn matches(entity_type: &EntityType) -> bool {
+                $first::matches(entity_type) $(&& $name::matches(entity_type))*
+            }
+
+            fn borrow(world: &World) -> Result<Self::Storages<'_>, BorrowError> {
+                Ok(($first::borrow(world)?, $($name::borrow(world)?,)*))
+            }
+
+            fn run_len(storages: &Self::Storages<'_>, entity_type: EntityTypeIndex, index: usize) -> usize {
+                let run_len = $first::run_len(&storages.$first_index, entity_type, index);
+                $(let run_len = cmp::min(run_len, $name::run_len(&storages.$index, entity_type, index));)*
+
+                run_len
+            }
+
+            fn chunks<'a>(storages: &'a mut Self::Storages<'_>, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+                #[allow(non_snake_case)]
+                let ($first, $($name,)*) = storages;
+                #[allow(non_snake_case)]
+                let ($first, $(mut $name,)*) = (
+                    $first::chunks($first, runs).into_iter(),
+                    $($name::chunks($name, runs).into_iter(),)*
+                );
+
+                $first
+                    .map(|chunk| (chunk, $($name.next().unwrap(),)*))
+                    .collect()
+            }
+
+            unsafe fn chunks_unchecked<'a>(world: &'a World, runs: &[Run]) -> Vec<Self::Chunk<'a>> {
+                #[allow(non_snake_case)]
+                let ($first, $(mut $name,)*) = (
+                    $first::chunks_unchecked(world, runs).into_iter(),
+                    $($name::chunks_unchecked(world, runs).into_iter(),)*
+                );
+
+                $first
+                    .map(|chunk| (chunk, $($name.next().unwrap(),)*))
+                    .collect()
+            }
+
+            fn chunk_len(chunk: &Self::Chunk<'_>) -> usize {
+                $first::chunk_len(&chunk.$first_index)
+            }
+
+            fn split_chunk(chunk: Self::Chunk<'_>, mid: usize) -> (Self::Chunk<'_>, Self::Chunk<'_>) {
+                #[allow(non_snake_case)]
+                let ($first, $($name,)*) = chunk;
+                #[allow(non_snake_case)]
+                let ($first, $($name,)*) = ($first::split_chunk($first, mid), $($name::split_chunk($name, mid),)*);
+
+                (($first.0, $($name.0,)*), ($first.1, $($name.1,)*))
+            }
+
+            fn next_item<'a>(chunk: &mut Self::Chunk<'a>) -> Option<Self::Item<'a>> {
+                Some(($first::next_item(&mut chunk.$first_index)?, $($name::next_item(&mut chunk.$index)?,)*))
+            }
+        }
+
+        unsafe impl<$first: ReadOnlyView $(, $name: ReadOnlyView)*> ReadOnlyView for ($first, $($name,)*) {}
+    };
+}
+
+impl_view_for_tuple!(A 0);
+impl_view_for_tuple!(A 0, B 1);
+impl_view_for_tuple!(A 0, B 1, C 2);
+impl_view_for_tuple!(A 0, B 1, C 2, D 3);
+impl_view_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_view_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_view_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_view_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Iterates over the entities of a world that have every component of the view
+/// `V`, such as `Query::<(Read<Position>, Write<Velocity>)>::new()`.
+///
+/// A query borrows the storages it fetches from through their borrow flags, so
+/// it only needs a shared reference to the world, and queries over different
+/// storages can run at the same time.
+pub struct Query<V: View> {
+    _marker: PhantomData<V>,
+}
+
+impl<V: View> Query<V> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow the storages of the view from the world, to iterate over the
+    /// matching entities chunk by chunk. Panics if the view conflicts with a
+    /// storage borrowed elsewhere, or with itself.
+    pub fn borrow<'w>(&self, world: &'w World) -> QueryBorrow<'w, V> {
+        let entity_types = world.entity_types()
+            .iter()
+            .filter(|entity_type| V::matches(entity_type))
+            .map(|entity_type| entity_type.index())
+            .collect();
+
+        QueryBorrow::new(world, Cow::Owned(entity_types))
+    }
+
+    /// Get the components of an entity through a shared borrow of the world,
+    /// or `None` if the entity does not exist or does not match the query. The
+    /// storages stay borrowed until the returned [`QueryRef`] is dropped.
+    /// Panics if the view conflicts with a storage borrowed elsewhere.
+    pub fn get<'w>(&self, world: &'w World, entity: Entity) -> Option<QueryRef<'w, V>>
+    where
+        V: ReadOnlyView,
+    {
+        let [location] = locate::<V, 1>(world, [entity])?;
+        let storages = match V::borrow(world) {
+            Ok(storages) => storages,
+            Err(BorrowError::MissingStorage(_)) => return None,
+            Err(error) => panic!("{}", error),
+        };
+
+        Some(QueryRef {
+            world,
+            location,
+            _storages: storages,
+        })
+    }
+
+    /// Get the components of an entity, or `None` if the entity does not exist
+    /// or does not match the query. Panics if the view conflicts with itself.
+    pub fn get_mut<'w>(&self, world: &'w mut World, entity: Entity) -> Option<V::Item<'w>> {
+        self.get_many_mut(world, [entity]).map(|[item]| item)
+    }
+
+    /// Get the components of several distinct entities at once. Returns `None`
+    /// if an entity does not exist or does not match the query, or if an entity
+    /// is listed twice. Panics if the view conflicts with itself.
+    pub fn get_many_mut<'w, const N: usize>(
+        &self,
+        world: &'w mut World,
+        entities: [Entity; N]
+    ) -> Option<[V::Item<'w>; N]>
+    {
+        // Nothing else can borrow the storages of an exclusively borrowed world,
+        // so borrowing them only checks that the view does not conflict with
+        // itself.
+        match V::borrow(world) {
+            Ok(_) => {}
+            Err(BorrowError::MissingStorage(_)) => return None,
+            Err(error) => panic!("{}", error),
+        }
+
+        let world: &'w World = world;
+        let locations = locate::<V, N>(world, entities)?;
+
+        // SAFETY: The world is borrowed exclusively for `'w`, and the view does
+        // not conflict with itself.
+        Some(fetch_many::<V, N>(locations, |runs| unsafe {
+            V::chunks_unchecked(world, runs)
+        }))
+    }
+
+    /// Call `f` with the components of every matching entity, entity type by
+    /// entity type. Panics if the view conflicts with a storage borrowed
+    /// elsewhere, or with itself.
+    pub fn for_each<F>(&self, world: &World, f: F)
+    where
+        F: for<'a> FnMut(V::Item<'a>),
+    {
+        self.borrow(world).for_each(f)
+    }
+
+    /// Call `f` with the components of every matching entity that passes the
+    /// relation filter. See [`QueryBorrow::for_each_related`].
+    pub fn for_each_related<R, F>(&self, world: &World, filter: RelatedTo<R>, f: F)
+    where
+        R: Component + Clone,
+        F: for<'a> FnMut(V::Item<'a>),
+    {
+        self.borrow(world).for_each_related(filter, f)
+    }
+
+    /// Call `f` with every chunk of matching entities. See [`QueryBorrow::chunks`].
+    pub fn for_each_chunk<F>(&self, world: &World, f: F)
+    where
+        F: for<'a> FnMut(QueryChunk<'a, V>),
+    {
+        self.borrow(world).for_each_chunk(f)
+    }
+
+    /// Like [`Query::for_each`], but spreads the entities over the rayon thread
+    /// pool. See [`QueryBorrow::par_for_each`].
+    #[cfg(feature = "parallel")]
+    pub fn par_for_each<F>(&self, world: &World, f: F)
+    where
+        F: for<'a> Fn(V::Item<'a>) + Send + Sync,
+    {
+        self.borrow(world).par_for_each(f)
+    }
+}
+
+impl<V: View> Default for Query<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A query that remembers which entity types match it.
+///
+/// Entity types are never changed once created, so each run only has to match
+/// the entity types created since the previous run. The cache is thrown away
+/// when the query runs on a different world, or after the entity types of the
+/// world were renumbered by [`World::remove_empty_entity_types`].
+pub struct PreparedQuery<V: View> {
+    layout_epoch: Option<u64>,
+    matched_len: usize,
+    entity_types: Vec<EntityTypeIndex>,
+    _marker: PhantomData<V>,
+}
+
+impl<V: View> PreparedQuery<V> {
+    pub fn new() -> Self {
+        Self {
+            layout_epoch: None,
+            matched_len: 0,
+            entity_types: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Bring the matching entity types up to date with the world.
+    fn update(&mut self, world: &World) {
+        if self.layout_epoch != Some(world.layout_epoch()) {
+            self.layout_epoch = Some(world.layout_epoch());
+            self.matched_len = 0;
+            self.entity_types.clear();
+        }
+
+        let new_entity_types = &world.entity_types()[self.matched_len..];
+        self.entity_types.extend(new_entity_types
+            .iter()
+            .filter(|entity_type| V::matches(entity_type))
+            .map(|entity_type| entity_type.index())
+        );
+        self.matched_len = world.entity_types().len();
+    }
+
+    /// The entity types matching the query as of its last run.
+    pub fn entity_types(&self) -> &[EntityTypeIndex] {
+        &self.entity_types
+    }
+
+    /// Like [`Query::borrow`], matching only the entity types created since the
+    /// previous run.
+    pub fn borrow<'a>(&'a mut self, world: &'a World) -> QueryBorrow<'a, V> {
+        self.update(world);
+
+        QueryBorrow::new(world, Cow::Borrowed(&self.entity_types))
+    }
+
+    pub fn for_each<F>(&mut self, world: &World, f: F)
+    where
+        F: for<'a> FnMut(V::Item<'a>),
+    {
+        self.borrow(world).for_each(f)
+    }
+
+    pub fn for_each_chunk<F>(&mut self, world: &World, f: F)
+    where
+        F: for<'a> FnMut(QueryChunk<'a, V>),
+    {
+        self.borrow(world).for_each_chunk(f)
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn par_for_each<F>(&mut self, world: &World, f: F)
+    where
+        F: for<'a> Fn(V::Item<'a>) + Send + Sync,
+    {
+        self.borrow(world).par_for_each(f)
+    }
+}
+
+impl<V: View> Default for PreparedQuery<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The storages of a view borrowed from a world, along with the entity types
+/// matching the view. The storages are released when the borrow is dropped.
+pub struct QueryBorrow<'a, V: View> {
+    world: &'a World,
+    entity_types: Cow<'a, [EntityTypeIndex]>,
+    storages: Option<V::Storages<'a>>,
+}
+
+impl<'a, V: View> QueryBorrow<'a, V> {
+    /// Borrow the storages of the view. Panics if the view conflicts with a
+    /// storage borrowed elsewhere, or with itself.
+    fn new(world: &'a World, entity_types: Cow<'a, [EntityTypeIndex]>) -> Self {
+        let storages = match V::borrow(world) {
+            Ok(storages) => Some(storages),
+            // Without a storage for one of the component types, no entity can match.
+            Err(BorrowError::MissingStorage(_)) => None,
+            Err(error) => panic!("{}", error),
+        };
+
+        Self {
+            world,
+            entity_types,
+            storages,
+        }
+    }
+
+    /// Iterate over the matching entities in runs of entities of the same
+    /// entity type. Every chunk holds the entities of a run along with a slice
+    /// of each component of the view, all in the same order. A run spans a
+    /// whole entity type unless a storage of the view splits it into chunks.
+    pub fn chunks(&mut self) -> impl Iterator<Item = QueryChunk<'_, V>> {
+        let world = self.world;
+        let (runs, chunks) = match self.storages.as_mut() {
+            Some(storages) => {
+                let runs = runs::<V>(world, storages, &self.entity_types);
+                let chunks = V::chunks(storages, &runs);
+
+                (runs, chunks)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+
+        runs
+            .into_iter()
+            .zip(chunks)
+            .map(move |((entity_type, range), components)| QueryChunk {
+                entities: &world.entity_types()[entity_type.id()].entities()[range],
+                components,
+            })
+    }
+
+    /// Get the components of an entity, or `None` if the entity does not exist
+    /// or does not match the query.
+    pub fn get(&mut self, entity: Entity) -> Option<V::Item<'_>> {
+        self.get_many_mut([entity]).map(|[item]| item)
+    }
+
+    /// Get the components of several distinct entities at once. Returns `None`
+    /// if an entity does not exist or does not match the query, or if an entity
+    /// is listed twice.
+    pub fn get_many_mut<const N: usize>(&mut self, entities: [Entity; N]) -> Option<[V::Item<'_>; N]> {
+        let locations = locate::<V, N>(self.world, entities)?;
+        let storages = self.storages.as_mut()?;
+
+        Some(fetch_many::<V, N>(locations, move |runs| V::chunks(storages, runs)))
+    }
+
+    pub fn for_each<F>(mut self, mut f: F)
+    where
+        F: for<'b> FnMut(V::Item<'b>),
+    {
+        for chunk in self.chunks() {
+            let mut components = chunk.into_components();
+            while let Some(item) = V::next_item(&mut components) {
+                f(item);
+            }
+        }
+    }
+
+    /// Call `f` with the components of every matching entity that is the source
+    /// of a relation of type `R` targeting the target of `filter`, in the order
+    /// the relations were added.
+    pub fn for_each_related<R, F>(mut self, filter: RelatedTo<R>, mut f: F)
+    where
+        R: Component + Clone,
+        F: for<'b> FnMut(V::Item<'b>),
+    {
+        let world = self.world;
+        let storages = match self.storages.as_mut() {
+            Some(storages) => storages,
+            None => return,
+        };
+
+        let runs: Vec<Run> = filter.sources(world)
+            .iter()
+            .filter_map(|source| world.location(*source))
+            .filter(|location| V::matches(&world.entity_types()[location.entity_type().id()]))
+            .map(|location| (location.entity_type(), location.component().id()..location.component().id() + 1))
+            .collect();
+        for mut components in V::chunks(storages, &runs) {
+            while let Some(item) = V::next_item(&mut components) {
+                f(item);
+            }
+        }
+    }
+
+    pub fn for_each_chunk<F>(mut self, mut f: F)
+    where
+        F: for<'b> FnMut(QueryChunk<'b, V>),
+    {
+        for chunk in self.chunks() {
+            f(chunk);
+        }
+    }
+
+    /// Spread the matching entities over the rayon thread pool in batches of at
+    /// most [`PARALLEL_BATCH_SIZE`] entities, so large entity types are split
+    /// across threads too.
+    #[cfg(feature = "parallel")]
+    pub fn par_for_each<F>(mut self, f: F)
+    where
+        F: for<'b> Fn(V::Item<'b>) + Send + Sync,
+    {
+        use rayon::iter::{
+            IntoParallelIterator,
+            ParallelIterator,
+        };
+
+        let mut batches = Vec::new();
+        for chunk in self.chunks() {
+            let mut components = chunk.into_components();
+            while V::chunk_len(&components) > PARALLEL_BATCH_SIZE {
+                let (batch, rest) = V::split_chunk(components, PARALLEL_BATCH_SIZE);
+                batches.push(batch);
+                components = rest;
+            }
+            if V::chunk_len(&components) > 0 {
+                batches.push(components);
+            }
+        }
+
+        batches.into_par_iter().for_each(|mut batch| {
+            while let Some(item) = V::next_item(&mut batch) {
+                f(item);
+            }
+        });
+    }
+}
+
+/// The components of an entity fetched by [`Query::get`], which keeps the
+/// storages of the view borrowed while it is alive.
+pub struct QueryRef<'w, V: ReadOnlyView> {
+    world: &'w World,
+    location: EntityLocation,
+    _storages: V::Storages<'w>,
+}
+
+impl<'w, V: ReadOnlyView> QueryRef<'w, V> {
+    /// The components of the entity, such as `(&Position, &Velocity)` for a
+    /// view `(Read<Position>, Read<Velocity>)`.
+    pub fn get(&self) -> V::Item<'_> {
+        // SAFETY: The view only reads components, and its storages are borrowed
+        // for as long as `self` is alive.
+        let [item] = fetch_many::<V, 1>([self.location], |runs| unsafe {
+            V::chunks_unchecked(self.world, runs)
+        });
+
+        item
+    }
+
+    pub fn location(&self) -> EntityLocation {
+        self.location
+    }
+}
+
+/// Find the locations of distinct entities matching the view `V`.
+fn locate<V: View, const N: usize>(world: &World, entities: [Entity; N]) -> Option<[EntityLocation; N]> {
+    for (i, entity) in entities.iter().enumerate() {
+        if entities[..i].contains(entity) {
+            return None;
+        }
+    }
+
+    let mut locations = [EntityLocation::new(EntityTypeIndex::new(0), ComponentIndex::new(0)); N];
+    for (location, entity) in locations.iter_mut().zip(entities.iter()) {
+        *location = world.location(*entity)?;
+        if !V::matches(&world.entity_types()[location.entity_type().id()]) {
+            return None;
+        }
+    }
+
+    Some(locations)
+}
+
+/// Split the matching entity types into runs that every storage of the view
+/// `V` holds contiguously.
+fn runs<V: View>(world: &World, storages: &V::Storages<'_>, entity_types: &[EntityTypeIndex]) -> Vec<Run> {
+    let mut runs = Vec::with_capacity(entity_types.len());
+    for entity_type in entity_types.iter() {
+        let len = world.entity_types()[entity_type.id()].entities().len();
+        let mut start = 0;
+        while start < len {
+            let run_len = V::run_len(storages, *entity_type, start);
+            debug_assert!(run_len > 0);
+            let end = start + cmp::min(run_len, len - start);
+            runs.push((*entity_type, start..end));
+            start = end;
+        }
+    }
+
+    runs
+}
+
+/// Take the components at distinct locations out of the chunks of runs of a
+/// single component each, which `chunks` gets in the order of the runs.
+fn fetch_many<'a, V: View, const N: usize>(
+    locations: [EntityLocation; N],
+    chunks: impl FnOnce(&[Run]) -> Vec<V::Chunk<'a>>,
+) -> [V::Item<'a>; N]
+{
+    let runs: Vec<Run> = locations
+        .iter()
+        .map(|location| (location.entity_type(), location.component().id()..location.component().id() + 1))
+        .collect();
+    let mut chunks = chunks(&runs).into_iter();
+
+    [(); N].map(|_| {
+        let mut chunk = chunks.next().unwrap();
+        V::next_item(&mut chunk).expect("An entity is missing from its entity type")
+    })
+}
+
+/// A run of entities of a single entity type matching a query, with their
+/// components.
+pub struct QueryChunk<'a, V: View> {
+    entities: &'a [Entity],
+    components: V::Chunk<'a>,
+}
+
+impl<'a, V: View> QueryChunk<'a, V> {
+    pub fn entities(&self) -> &'a [Entity] {
+        self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// The component slices of the chunk, such as `(&[Position], &mut [Velocity])`
+    /// for a view `(Read<Position>, Write<Velocity>)`.
+    pub fn into_components(self) -> V::Chunk<'a> {
+        self.components
+    }
+
+    pub fn into_parts(self) -> (&'a [Entity], V::Chunk<'a>) {
+        (self.entities, self.components)
+    }
+}
@@ -0,0 +1,304 @@
+use crate::component::{
+    Component,
+    ComponentTypeIndex,
+};
+use crate::entity::{
+    Entity,
+};
+#[cfg(feature = "serialize")]
+use crate::prefab::{
+    Prefab,
+};
+use crate::sparse::{
+    OpaqueSparseStorage,
+    SparseSetStorage,
+};
+use crate::storage::{
+    ComponentIndex,
+    EntityTypeIndex,
+    OpaqueComponentStorage,
+};
+#[cfg(feature = "serialize")]
+use crate::storage::{
+    StoreComponentsIn,
+};
+use std::collections::{
+    HashMap,
+};
+
+
+/// A component that holds references to other entities.
+///
+/// When entities change ids, e.g. because they were merged into another world,
+/// the world calls `map_entities` on the components of the moved entities so
+/// that the references they hold follow the entities to their new ids.
+pub trait MapEntities {
+    /// Replace every entity held by the component by its entry in `entity_map`.
+    /// Entities missing from the map should be left untouched.
+    fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>);
+}
+
+/// The type erased operations registered for a component type.
+#[derive(Copy, Clone, Debug)]
+pub struct ComponentRegistration {
+    map_entities: Option<MapEntitiesFns>,
+    clone: Option<CloneFns>,
+    #[cfg(feature = "serialize")]
+    deserialize: Option<DeserializeFn>,
+}
+
+impl ComponentRegistration {
+    fn new() -> Self {
+        Self {
+            map_entities: None,
+            clone: None,
+            #[cfg(feature = "serialize")]
+            deserialize: None,
+        }
+    }
+
+    /// Determine whether the component type has registered a [`MapEntities`] implementation.
+    pub fn maps_entities(&self) -> bool {
+        self.map_entities.is_some()
+    }
+
+    pub(crate) fn map_entities_fns(&self) -> Option<MapEntitiesFns> {
+        self.map_entities
+    }
+
+    /// Determine whether the component type has registered a `Clone` implementation.
+    pub fn is_cloneable(&self) -> bool {
+        self.clone.is_some()
+    }
+
+    pub(crate) fn clone_fns(&self) -> Option<CloneFns> {
+        self.clone
+    }
+
+    #[cfg(feature = "serialize")]
+    pub(crate) fn deserialize_fn(&self) -> Option<DeserializeFn> {
+        self.deserialize
+    }
+
+    /// Fill in the operations registered in `other` but missing from this registration.
+    fn merge(&mut self, other: &ComponentRegistration) {
+        if self.map_entities.is_none() {
+            self.map_entities = other.map_entities;
+        }
+        if self.clone.is_none() {
+            self.clone = other.clone;
+        }
+        #[cfg(feature = "serialize")]
+        if self.deserialize.is_none() {
+            self.deserialize = other.deserialize;
+        }
+    }
+}
+
+/// The functions mapping the entities of a component, either in place in a
+/// component storage or in a sparse set storage.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct MapEntitiesFns {
+    pub(crate) map_component: unsafe fn(*mut u8, &HashMap<Entity, Entity>),
+    pub(crate) map_sparse: fn(&mut dyn OpaqueSparseStorage, &[Entity], &HashMap<Entity, Entity>),
+}
+
+unsafe fn map_component<T>(ptr: *mut u8, entity_map: &HashMap<Entity, Entity>)
+where
+    T: Component + MapEntities,
+{
+    (*(ptr as *mut T)).map_entities(entity_map);
+}
+
+fn map_sparse<T>(storage: &mut dyn OpaqueSparseStorage, entities: &[Entity], entity_map: &HashMap<Entity, Entity>)
+where
+    T: Component + MapEntities,
+{
+    let storage = storage.downcast_mut::<SparseSetStorage<T>>().unwrap();
+    for entity in entities.iter() {
+        if let Some(component) = storage.get_mut(*entity) {
+            component.map_entities(entity_map);
+        }
+    }
+}
+
+/// The functions cloning the components of a component type out of type erased 
+/// storages.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct CloneFns {
+    /// Clone one component of an entity type and append the clone to another
+    /// entity type in the same storage.
+    pub(crate) clone_component: fn(&mut dyn OpaqueComponentStorage, EntityTypeIndex, ComponentIndex, EntityTypeIndex),
+    /// Clone every component of an entity type into the same entity type in 
+    /// another storage of the same component type.
+    pub(crate) clone_entity_type: fn(&dyn OpaqueComponentStorage, EntityTypeIndex, &mut dyn OpaqueComponentStorage),
+    /// Clone the sparse component of one entity for another entity.
+    pub(crate) clone_sparse_component: fn(&mut dyn OpaqueSparseStorage, Entity, Entity),
+    /// Clone a whole sparse set storage.
+    pub(crate) clone_sparse: fn(&dyn OpaqueSparseStorage) -> Box<dyn OpaqueSparseStorage>,
+}
+
+/// Append clones to an entity type of a storage. The storage takes ownership 
+/// of the clones.
+pub(crate) fn extend_clones<T>(storage: &mut dyn OpaqueComponentStorage, entity_type: EntityTypeIndex, mut clones: Vec<T>)
+where
+    T: Component,
+{
+    unsafe {
+        storage.extend_memcopy_raw(entity_type, clones.as_ptr() as *const u8, clones.len());
+        clones.set_len(0);
+    }
+}
+
+fn clone_component<T>(
+    storage: &mut dyn OpaqueComponentStorage,
+    src: EntityTypeIndex,
+    index: ComponentIndex,
+    dst: EntityTypeIndex,
+)
+where
+    T: Component + Clone,
+{
+    let ptr = storage.get_component_ptr(src, index).unwrap();
+    let clone = unsafe { (*(ptr as *const T)).clone() };
+    extend_clones(storage, dst, vec![clone]);
+}
+
+fn clone_entity_type<T>(
+    src_storage: &dyn OpaqueComponentStorage,
+    entity_type: EntityTypeIndex,
+    dst_storage: &mut dyn OpaqueComponentStorage,
+)
+where
+    T: Component + Clone,
+{
+    let count = src_storage.count(entity_type).unwrap_or(0);
+    let clones: Vec<T> = (0..count)
+        .map(|index| {
+            let ptr = src_storage.get_component_ptr(entity_type, ComponentIndex::new(index)).unwrap();
+            unsafe { (*(ptr as *const T)).clone() }
+        })
+        .collect();
+    extend_clones(dst_storage, entity_type, clones);
+}
+
+fn clone_sparse_component<T>(storage: &mut dyn OpaqueSparseStorage, src: Entity, dst: Entity)
+where
+    T: Component + Clone,
+{
+    let storage = storage.downcast_mut::<SparseSetStorage<T>>().unwrap();
+    if let Some(clone) = storage.get(src).cloned() {
+        storage.insert(dst, clone);
+    }
+}
+
+fn clone_sparse<T>(storage: &dyn OpaqueSparseStorage) -> Box<dyn OpaqueSparseStorage>
+where
+    T: Component + Clone,
+{
+    let storage = storage.downcast_ref::<SparseSetStorage<T>>().unwrap();
+
+    Box::new(storage.clone())
+}
+
+/// Deserialize a component and add it to a prefab.
+#[cfg(feature = "serialize")]
+pub(crate) type DeserializeFn = fn(&mut Prefab, &mut dyn erased_serde::Deserializer<'_>) -> Result<(), erased_serde::Error>;
+
+#[cfg(feature = "serialize")]
+fn deserialize_component<T>(prefab: &mut Prefab, deserializer: &mut dyn erased_serde::Deserializer<'_>) -> Result<(), erased_serde::Error>
+where
+    T: Component + Clone + StoreComponentsIn + serde::de::DeserializeOwned,
+{
+    prefab.insert(erased_serde::deserialize::<T>(deserializer)?);
+
+    Ok(())
+}
+
+/// The registry of the component types with behavior the world has to invoke
+/// without knowing the concrete component type.
+#[derive(Clone, Debug, Default)]
+pub struct ComponentRegistry {
+    registrations: HashMap<ComponentTypeIndex, ComponentRegistration>,
+    /// The component types by the names they are deserialized under.
+    #[cfg(feature = "serialize")]
+    names: HashMap<String, ComponentTypeIndex>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self {
+            registrations: HashMap::new(),
+            #[cfg(feature = "serialize")]
+            names: HashMap::new(),
+        }
+    }
+
+    fn registration_mut(&mut self, component_type: ComponentTypeIndex) -> &mut ComponentRegistration {
+        self.registrations
+            .entry(component_type)
+            .or_insert_with(ComponentRegistration::new)
+    }
+
+    /// Register the [`MapEntities`] implementation of `T`.
+    pub fn register_map_entities<T: Component + MapEntities>(&mut self) {
+        let registration = self.registration_mut(ComponentTypeIndex::of::<T>());
+        registration.map_entities = Some(MapEntitiesFns {
+            map_component: map_component::<T>,
+            map_sparse: map_sparse::<T>,
+        });
+    }
+
+    /// Register the `Clone` implementation of `T`, so that the world can clone 
+    /// `T` components in type erased storages.
+    pub fn register_clone<T: Component + Clone>(&mut self) {
+        let registration = self.registration_mut(ComponentTypeIndex::of::<T>());
+        registration.clone = Some(CloneFns {
+            clone_component: clone_component::<T>,
+            clone_entity_type: clone_entity_type::<T>,
+            clone_sparse_component: clone_sparse_component::<T>,
+            clone_sparse: clone_sparse::<T>,
+        });
+    }
+
+    /// Register the `Deserialize` implementation of `T` under `name`, so that
+    /// prefabs loaded from data can hold `T` components. See [`Prefab::load`].
+    #[cfg(feature = "serialize")]
+    pub fn register_deserialize<T>(&mut self, name: &str)
+    where
+        T: Component + Clone + StoreComponentsIn + serde::de::DeserializeOwned,
+    {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.registration_mut(component_type).deserialize = Some(deserialize_component::<T>);
+        self.names.insert(name.to_string(), component_type);
+    }
+
+    /// The deserialize function of the component type registered under `name`.
+    #[cfg(feature = "serialize")]
+    pub(crate) fn deserialize_fn(&self, name: &str) -> Option<DeserializeFn> {
+        self.names
+            .get(name)
+            .and_then(|component_type| self.get(*component_type))
+            .and_then(|registration| registration.deserialize_fn())
+    }
+
+    /// Add the registrations of `other` to the registry, keeping the existing 
+    /// registrations where both registries registered an operation.
+    pub fn merge(&mut self, other: &ComponentRegistry) {
+        for (component_type, registration) in other.registrations.iter() {
+            self.registration_mut(*component_type).merge(registration);
+        }
+        #[cfg(feature = "serialize")]
+        for (name, component_type) in other.names.iter() {
+            self.names.entry(name.clone()).or_insert(*component_type);
+        }
+    }
+
+    pub fn get(&self, component_type: ComponentTypeIndex) -> Option<&ComponentRegistration> {
+        self.registrations.get(&component_type)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ComponentTypeIndex, &ComponentRegistration)> {
+        self.registrations.iter()
+    }
+}
@@ -0,0 +1,525 @@
+use crate::component::{
+    Component,
+    ComponentTypeIndex,
+};
+use crate::entity::{
+    Entity,
+};
+use crate::storage::{
+    ComponentIndex,
+};
+use crate::world::{
+    World,
+};
+use downcast::{
+    Downcast,
+    impl_downcast,
+};
+use std::collections::{
+    HashMap,
+};
+use std::iter;
+use std::slice;
+
+
+/// What happens to the relations targeting an entity when the entity is removed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RelationCleanup {
+    /// Remove the relations, leaving their sources alive.
+    #[default]
+    RemoveRelation,
+    /// Remove the sources of the relations as well.
+    RemoveSource,
+}
+
+/// A type erased relation storage.
+pub trait OpaqueRelationStorage: Downcast + Send + Sync {
+    fn cleanup(&self) -> RelationCleanup;
+
+    /// Remove every relation from or to an entity. Returns the sources of the
+    /// removed relations that targeted the entity.
+    fn remove_entity(&mut self, entity: Entity) -> Vec<Entity>;
+
+    /// Replace the sources and targets of every relation in the storage by their
+    /// entries in `entity_map`. Entities missing from the map are left untouched.
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>);
+
+    /// Move every relation of the storage into `dst`, a storage of the same type,
+    /// replacing sources and targets by their entries in `entity_map`.
+    fn transfer_entities(&mut self, entity_map: &HashMap<Entity, Entity>, dst: &mut dyn OpaqueRelationStorage);
+
+    /// Give `dst` clones of the relations of which `src` is the source.
+    fn clone_source(&mut self, src: Entity, dst: Entity);
+
+    fn clone_storage(&self) -> Box<dyn OpaqueRelationStorage>;
+
+    /// Remove every relation from the storage.
+    fn clear(&mut self);
+
+    /// Return the number of relations in the storage.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl_downcast!(OpaqueRelationStorage);
+
+
+/// The storage of one relation type. Each relation goes from a source entity to
+/// a target entity and carries a value of type `R`. An entity can be the source
+/// of relations of the same type to any number of targets.
+///
+/// The storage indexes the relations both by source and by target, so finding
+/// the sources of the relations targeting an entity is as cheap as finding the
+/// targets of an entity.
+#[derive(Clone, Debug)]
+pub struct RelationStorage<R: Component> {
+    targets: HashMap<Entity, Vec<(Entity, R)>>,
+    sources: HashMap<Entity, Vec<Entity>>,
+    length: usize,
+    cleanup: RelationCleanup,
+}
+
+impl<R> RelationStorage<R>
+where
+    R: Component,
+{
+    pub fn new(cleanup: RelationCleanup) -> Self {
+        Self {
+            targets: HashMap::new(),
+            sources: HashMap::new(),
+            length: 0,
+            cleanup,
+        }
+    }
+
+    pub fn cleanup(&self) -> RelationCleanup {
+        self.cleanup
+    }
+
+    pub fn set_cleanup(&mut self, cleanup: RelationCleanup) {
+        self.cleanup = cleanup;
+    }
+
+    /// Relate `source` to `target`, returning the previous value of the relation
+    /// if the entities were already related.
+    pub fn insert(&mut self, source: Entity, target: Entity, relation: R) -> Option<R> {
+        let targets = self.targets.entry(source).or_default();
+        if let Some((_, existing)) = targets.iter_mut().find(|(existing, _)| *existing == target) {
+            return Some(std::mem::replace(existing, relation));
+        }
+
+        targets.push((target, relation));
+        self.sources.entry(target).or_default().push(source);
+        self.length += 1;
+
+        None
+    }
+
+    /// Remove the relation from `source` to `target`, returning its value if the
+    /// entities were related.
+    pub fn remove(&mut self, source: Entity, target: Entity) -> Option<R> {
+        let targets = self.targets.get_mut(&source)?;
+        let index = targets.iter().position(|(existing, _)| *existing == target)?;
+        let (_, relation) = targets.swap_remove(index);
+        if targets.is_empty() {
+            self.targets.remove(&source);
+        }
+        Self::remove_entry(&mut self.sources, target, source);
+        self.length -= 1;
+
+        Some(relation)
+    }
+
+    fn remove_entry(index: &mut HashMap<Entity, Vec<Entity>>, key: Entity, entity: Entity) {
+        if let Some(entities) = index.get_mut(&key) {
+            entities.retain(|existing| *existing != entity);
+            if entities.is_empty() {
+                index.remove(&key);
+            }
+        }
+    }
+
+    pub fn get(&self, source: Entity, target: Entity) -> Option<&R> {
+        self.targets
+            .get(&source)?
+            .iter()
+            .find(|(existing, _)| *existing == target)
+            .map(|(_, relation)| relation)
+    }
+
+    pub fn get_mut(&mut self, source: Entity, target: Entity) -> Option<&mut R> {
+        self.targets
+            .get_mut(&source)?
+            .iter_mut()
+            .find(|(existing, _)| *existing == target)
+            .map(|(_, relation)| relation)
+    }
+
+    pub fn contains(&self, source: Entity, target: Entity) -> bool {
+        self.get(source, target).is_some()
+    }
+
+    /// Determine whether an entity is the source of any relation in the storage.
+    pub fn has_targets(&self, source: Entity) -> bool {
+        self.targets.contains_key(&source)
+    }
+
+    /// The targets of the relations of an entity, along with the relations.
+    pub fn targets(&self, source: Entity) -> impl Iterator<Item = (Entity, &R)> {
+        self.targets
+            .get(&source)
+            .map(|targets| targets.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .map(|(target, relation)| (*target, relation))
+    }
+
+    /// The sources of the relations targeting an entity.
+    pub fn sources(&self, target: Entity) -> &[Entity] {
+        self.sources
+            .get(&target)
+            .map(|sources| sources.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Join the entities of an entity type against the relations targeting
+    /// `target`.
+    ///
+    /// The iterator yields the component index of each entity in `entities` that
+    /// is related to `target`, along with the relation, so the entity type's
+    /// columns can be filtered by the relation's target.
+    pub fn join<'a>(&'a self, entities: &'a [Entity], target: Entity) -> RelationJoin<'a, R> {
+        RelationJoin {
+            storage: self,
+            target,
+            entities: entities.iter().enumerate(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+impl<R> OpaqueRelationStorage for RelationStorage<R>
+where
+    R: Component + Clone,
+{
+    fn cleanup(&self) -> RelationCleanup {
+        self.cleanup
+    }
+
+    fn remove_entity(&mut self, entity: Entity) -> Vec<Entity> {
+        if let Some(targets) = self.targets.remove(&entity) {
+            self.length -= targets.len();
+            for (target, _) in targets {
+                Self::remove_entry(&mut self.sources, target, entity);
+            }
+        }
+
+        let sources = self.sources.remove(&entity).unwrap_or_default();
+        for source in sources.iter() {
+            if let Some(targets) = self.targets.get_mut(source) {
+                targets.retain(|(target, _)| *target != entity);
+                if targets.is_empty() {
+                    self.targets.remove(source);
+                }
+            }
+        }
+        self.length -= sources.len();
+
+        sources
+    }
+
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        let mut remapped = RelationStorage::new(self.cleanup);
+        self.transfer_entities(entity_map, &mut remapped);
+        *self = remapped;
+    }
+
+    fn transfer_entities(&mut self, entity_map: &HashMap<Entity, Entity>, dst: &mut dyn OpaqueRelationStorage) {
+        let dst_storage = dst.downcast_mut::<Self>().unwrap();
+        let map = |entity: Entity| entity_map.get(&entity).copied().unwrap_or(entity);
+        self.sources.clear();
+        self.length = 0;
+        for (source, targets) in self.targets.drain() {
+            for (target, relation) in targets {
+                dst_storage.insert(map(source), map(target), relation);
+            }
+        }
+    }
+
+    fn clone_source(&mut self, src: Entity, dst: Entity) {
+        let relations: Vec<(Entity, R)> = self.targets
+            .get(&src)
+            .cloned()
+            .unwrap_or_default();
+        for (target, relation) in relations {
+            self.insert(dst, target, relation);
+        }
+    }
+
+    fn clone_storage(&self) -> Box<dyn OpaqueRelationStorage> {
+        Box::new(self.clone())
+    }
+
+    fn clear(&mut self) {
+        self.targets.clear();
+        self.sources.clear();
+        self.length = 0;
+    }
+
+    fn len(&self) -> usize {
+        RelationStorage::len(self)
+    }
+}
+
+/// An iterator joining the entities of an entity type with the relations
+/// targeting one entity.
+pub struct RelationJoin<'a, R: Component> {
+    storage: &'a RelationStorage<R>,
+    target: Entity,
+    entities: iter::Enumerate<slice::Iter<'a, Entity>>,
+}
+
+impl<'a, R> Iterator for RelationJoin<'a, R>
+where
+    R: Component,
+{
+    type Item = (ComponentIndex, &'a R);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let storage = self.storage;
+        let target = self.target;
+        self.entities
+            .by_ref()
+            .find_map(|(index, entity)| {
+                storage.get(*entity, target).map(|relation| (ComponentIndex::new(index), relation))
+            })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.entities.size_hint().1)
+    }
+}
+
+/// The relation storages of a world, one for each relation type.
+#[derive(Debug, Default)]
+pub struct RelationMap {
+    data: HashMap<ComponentTypeIndex, Box<dyn OpaqueRelationStorage>>,
+}
+
+impl RelationMap {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: HashMap::new(),
+        }
+    }
+
+    fn get_or_insert<R: Component + Clone>(&mut self) -> &mut RelationStorage<R> {
+        let relation_type = ComponentTypeIndex::of::<R>();
+        self.data
+            .entry(relation_type)
+            .or_insert_with(|| Box::new(RelationStorage::<R>::new(RelationCleanup::default())))
+            .downcast_mut()
+            .unwrap()
+    }
+
+    pub fn get<R: Component + Clone>(&self) -> Option<&RelationStorage<R>> {
+        let relation_type = ComponentTypeIndex::of::<R>();
+        self.data
+            .get(&relation_type)
+            .and_then(|storage| storage.downcast_ref())
+    }
+
+    pub fn get_mut<R: Component + Clone>(&mut self) -> Option<&mut RelationStorage<R>> {
+        let relation_type = ComponentTypeIndex::of::<R>();
+        self.data
+            .get_mut(&relation_type)
+            .and_then(|storage| storage.downcast_mut())
+    }
+
+    /// Remove every relation from or to an entity. Returns the sources that have
+    /// to be removed along with the entity.
+    pub(crate) fn remove_entity(&mut self, entity: Entity) -> Vec<Entity> {
+        let mut removed_sources = Vec::new();
+        for storage in self.data.values_mut() {
+            let sources = storage.remove_entity(entity);
+            if storage.cleanup() == RelationCleanup::RemoveSource {
+                removed_sources.extend(sources);
+            }
+        }
+
+        removed_sources
+    }
+
+    /// Remove every relation, keeping the cleanup policy of each relation type.
+    pub(crate) fn clear(&mut self) {
+        for storage in self.data.values_mut() {
+            storage.clear();
+        }
+    }
+
+    /// Move every relation of `other` into this map, renaming the entities by
+    /// `entity_map`.
+    pub(crate) fn merge_from(&mut self, other: &mut RelationMap, entity_map: &HashMap<Entity, Entity>) {
+        for (relation_type, mut storage) in other.data.drain() {
+            match self.data.get_mut(&relation_type) {
+                Some(dst_storage) => {
+                    storage.transfer_entities(entity_map, dst_storage.as_mut());
+                }
+                None => {
+                    storage.remap_entities(entity_map);
+                    self.data.insert(relation_type, storage);
+                }
+            }
+        }
+    }
+
+    /// Give `dst` clones of the relations of which `src` is the source.
+    pub(crate) fn clone_source(&mut self, src: Entity, dst: Entity) {
+        for storage in self.data.values_mut() {
+            storage.clone_source(src, dst);
+        }
+    }
+}
+
+impl Clone for RelationMap {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data
+                .iter()
+                .map(|(relation_type, storage)| (*relation_type, storage.clone_storage()))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Debug for dyn OpaqueRelationStorage {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "OpaqueRelationStorage")
+    }
+}
+
+impl World {
+    /// Relate `source` to `target` with a relation of type `R`, replacing the
+    /// previous relation of type `R` between them. Returns `false` if either
+    /// entity does not exist, in which case the relation is dropped.
+    pub fn add_relation<R: Component + Clone>(&mut self, source: Entity, relation: R, target: Entity) -> bool {
+        if !self.contains(source) || !self.contains(target) {
+            return false;
+        }
+
+        self.relations_mut().get_or_insert::<R>().insert(source, target, relation);
+
+        true
+    }
+
+    /// Remove the relation of type `R` from `source` to `target`, returning it if
+    /// the entities were related.
+    pub fn remove_relation<R: Component + Clone>(&mut self, source: Entity, target: Entity) -> Option<R> {
+        self.relations_mut()
+            .get_mut::<R>()
+            .and_then(|storage| storage.remove(source, target))
+    }
+
+    pub fn get_relation<R: Component + Clone>(&self, source: Entity, target: Entity) -> Option<&R> {
+        self.relations()
+            .get::<R>()
+            .and_then(|storage| storage.get(source, target))
+    }
+
+    pub fn has_relation<R: Component + Clone>(&self, source: Entity, target: Entity) -> bool {
+        self.get_relation::<R>(source, target).is_some()
+    }
+
+    /// The sources of the relations of type `R` targeting an entity.
+    pub fn relation_sources<R: Component + Clone>(&self, target: Entity) -> &[Entity] {
+        self.relations()
+            .get::<R>()
+            .map(|storage| storage.sources(target))
+            .unwrap_or(&[])
+    }
+
+    /// The targets of the relations of type `R` of an entity.
+    pub fn relation_targets<R: Component + Clone>(&self, source: Entity) -> Vec<Entity> {
+        self.relations()
+            .get::<R>()
+            .map(|storage| storage.targets(source).map(|(target, _)| target).collect())
+            .unwrap_or_default()
+    }
+
+    /// Set what happens to the relations of type `R` targeting an entity when
+    /// the entity is removed.
+    pub fn set_relation_cleanup<R: Component + Clone>(&mut self, cleanup: RelationCleanup) {
+        self.relations_mut().get_or_insert::<R>().set_cleanup(cleanup);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::{
+        EntityAllocator,
+    };
+    use crate::storage::{
+        ComponentIndex,
+    };
+    use super::{
+        OpaqueRelationStorage,
+        RelationCleanup,
+        RelationStorage,
+    };
+
+
+    #[test]
+    fn test_relation_storage_reverse_lookup() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = RelationStorage::<u32>::new(RelationCleanup::RemoveRelation);
+        let entities: Vec<_> = allocator.by_ref().take(3).collect();
+        storage.insert(entities[0], entities[2], 0);
+        storage.insert(entities[1], entities[2], 1);
+        storage.insert(entities[0], entities[1], 2);
+
+        assert_eq!(storage.sources(entities[2]), &[entities[0], entities[1]]);
+        assert_eq!(storage.sources(entities[1]), &[entities[0]]);
+        assert_eq!(storage.insert(entities[0], entities[2], 3), Some(0));
+        assert_eq!(storage.len(), 3);
+    }
+
+    #[test]
+    fn test_relation_storage_remove_entity() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = RelationStorage::<u32>::new(RelationCleanup::RemoveRelation);
+        let entities: Vec<_> = allocator.by_ref().take(3).collect();
+        storage.insert(entities[0], entities[1], 0);
+        storage.insert(entities[1], entities[2], 1);
+        storage.insert(entities[2], entities[1], 2);
+        let sources = storage.remove_entity(entities[1]);
+
+        assert_eq!(sources, vec![entities[0], entities[2]]);
+        assert!(storage.is_empty());
+        assert!(storage.sources(entities[2]).is_empty());
+    }
+
+    #[test]
+    fn test_relation_join() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = RelationStorage::<u32>::new(RelationCleanup::RemoveRelation);
+        let entities: Vec<_> = allocator.by_ref().take(4).collect();
+        storage.insert(entities[1], entities[0], 1);
+        storage.insert(entities[2], entities[3], 2);
+        storage.insert(entities[3], entities[0], 3);
+        let result: Vec<_> = storage.join(&entities, entities[0]).collect();
+        let expected = vec![(ComponentIndex::new(1), &1), (ComponentIndex::new(3), &3)];
+
+        assert_eq!(result, expected);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::component::{
+    Component,
+};
+use crate::entity::{
+    Entity,
+};
+use crate::storage::{
+    ComponentIndex,
+    ComponentMetadata,
+};
+use downcast::{
+    Downcast,
+    impl_downcast,
+};
+use std::collections::{
+    HashMap,
+};
+use std::iter;
+use std::mem;
+use std::slice;
+
+
+/// A type erased sparse set storage.
+pub trait OpaqueSparseStorage: Downcast + Send + Sync {
+    fn metadata(&self) -> ComponentMetadata;
+
+    /// Determine whether an entity has a component in the storage.
+    fn contains(&self, entity: Entity) -> bool;
+
+    /// Remove and drop the component of an entity, if it has one. Returns `true`
+    /// if a component was removed.
+    fn remove_entity(&mut self, entity: Entity) -> bool;
+
+    /// Release the excess capacity of the storage.
+    fn shrink_to_fit(&mut self);
+
+    /// Drop every component in the storage, keeping its capacity.
+    fn clear(&mut self);
+
+    /// Replace the entity of every component in the storage by its entry in 
+    /// `entity_map`. Entities missing from the map keep their component.
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>);
+
+    /// Move every component of the storage into `dst`, a storage of the same type,
+    /// replacing the entity of each component by its entry in `entity_map`.
+    fn transfer_entities(&mut self, entity_map: &HashMap<Entity, Entity>, dst: &mut dyn OpaqueSparseStorage);
+
+    /// Return the number of components the storage can hold without reallocating.
+    fn capacity(&self) -> usize;
+
+    /// Return the number of components in the storage.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl_downcast!(OpaqueSparseStorage);
+
+
+/// A component storage keyed directly by entity.
+///
+/// Adding or removing a component in a sparse set does not change the entity type
+/// of the entity, so no components move between entity types. This makes sparse
+/// sets suitable for components that get attached and detached at high frequency,
+/// e.g. tags, timers and markers.
+///
+/// The sparse index maps each entity with a component to its dense index, so the
+/// memory of the storage follows the number of components rather than the largest
+/// entity id ever allocated.
+#[derive(Clone, Debug)]
+pub struct SparseSetStorage<T: Component> {
+    sparse: HashMap<Entity, usize>,
+    entities: Vec<Entity>,
+    components: Vec<T>,
+}
+
+impl<T> SparseSetStorage<T>
+where
+    T: Component,
+{
+    pub fn new() -> Self {
+        Self {
+            sparse: HashMap::new(),
+            entities: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn dense_index(&self, entity: Entity) -> Option<usize> {
+        self.sparse.get(&entity).copied()
+    }
+
+    /// Insert a component for an entity, returning the previous component
+    /// of the entity if it had one.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(dense_index) = self.dense_index(entity) {
+            return Some(mem::replace(&mut self.components[dense_index], component));
+        }
+
+        self.sparse.insert(entity, self.entities.len());
+        self.entities.push(entity);
+        self.components.push(component);
+
+        None
+    }
+
+    /// Remove the component of an entity, returning it if the entity had one.
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let dense_index = self.sparse.remove(&entity)?;
+        self.entities.swap_remove(dense_index);
+        let component = self.components.swap_remove(dense_index);
+        if dense_index < self.entities.len() {
+            // The last entity was swapped into the slot of the removed entity.
+            let swapped = self.entities[dense_index];
+            self.sparse.insert(swapped, dense_index);
+        }
+
+        Some(component)
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.dense_index(entity).is_some()
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.dense_index(entity).map(move |dense_index| &self.components[dense_index])
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.dense_index(entity).map(move |dense_index| &mut self.components[dense_index])
+    }
+
+    /// The entities that have a component in the storage, in storage order.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    /// The components in the storage, in the same order as [`SparseSetStorage::entities`].
+    pub fn components(&self) -> &[T] {
+        &self.components
+    }
+
+    pub fn components_mut(&mut self) -> &mut [T] {
+        &mut self.components
+    }
+
+    pub fn iter(&self) -> iter::Zip<iter::Copied<slice::Iter<'_, Entity>>, slice::Iter<'_, T>> {
+        self.entities.iter().copied().zip(self.components.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> iter::Zip<iter::Copied<slice::Iter<'_, Entity>>, slice::IterMut<'_, T>> {
+        self.entities.iter().copied().zip(self.components.iter_mut())
+    }
+
+    /// Join the entities of an entity type against the sparse set.
+    ///
+    /// The iterator yields the component index of each entity in `entities` that
+    /// has a component in the sparse set, along with the component. The component
+    /// index addresses the entity's components in the entity type's component
+    /// views, so the sparse components can be matched up with the entity type's
+    /// columns.
+    pub fn join<'a>(&'a self, entities: &'a [Entity]) -> SparseJoin<'a, T> {
+        SparseJoin {
+            storage: self,
+            entities: entities.iter().enumerate(),
+        }
+    }
+
+    /// Release the excess capacity of the storage.
+    pub fn shrink_to_fit(&mut self) {
+        self.sparse.shrink_to_fit();
+        self.entities.shrink_to_fit();
+        self.components.shrink_to_fit();
+    }
+
+    /// Remove every component from the storage, keeping its capacity.
+    pub fn clear(&mut self) {
+        self.sparse.clear();
+        self.entities.clear();
+        self.components.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+impl<T> Default for SparseSetStorage<T>
+where
+    T: Component,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> OpaqueSparseStorage for SparseSetStorage<T>
+where
+    T: Component,
+{
+    fn metadata(&self) -> ComponentMetadata {
+        ComponentMetadata::of::<T>()
+    }
+
+    fn contains(&self, entity: Entity) -> bool {
+        SparseSetStorage::contains(self, entity)
+    }
+
+    fn remove_entity(&mut self, entity: Entity) -> bool {
+        self.remove(entity).is_some()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        SparseSetStorage::shrink_to_fit(self)
+    }
+
+    fn clear(&mut self) {
+        SparseSetStorage::clear(self)
+    }
+
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        let mut remapped = SparseSetStorage::new();
+        self.transfer_entities(entity_map, &mut remapped);
+        *self = remapped;
+    }
+
+    fn transfer_entities(&mut self, entity_map: &HashMap<Entity, Entity>, dst: &mut dyn OpaqueSparseStorage) {
+        let dst_storage = dst.downcast_mut::<Self>().unwrap();
+        self.sparse.clear();
+        let entities = self.entities.drain(..);
+        let components = self.components.drain(..);
+        for (entity, component) in entities.zip(components) {
+            let new_entity = entity_map.get(&entity).copied().unwrap_or(entity);
+            dst_storage.insert(new_entity, component);
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.components.capacity()
+    }
+
+    fn len(&self) -> usize {
+        SparseSetStorage::len(self)
+    }
+}
+
+/// An iterator joining the entities of an entity type with a sparse set.
+pub struct SparseJoin<'a, T: Component> {
+    storage: &'a SparseSetStorage<T>,
+    entities: iter::Enumerate<slice::Iter<'a, Entity>>,
+}
+
+impl<'a, T> Iterator for SparseJoin<'a, T>
+where
+    T: Component,
+{
+    type Item = (ComponentIndex, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let storage = self.storage;
+        self.entities
+            .by_ref()
+            .find_map(|(index, entity)| {
+                storage.get(*entity).map(|component| (ComponentIndex::new(index), component))
+            })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.entities.size_hint().1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::{
+        EntityAllocator,
+    };
+    use crate::storage::{
+        ComponentIndex,
+    };
+    use super::{
+        SparseSetStorage,
+    };
+
+
+    #[test]
+    fn test_empty_sparse_set() {
+        let storage = SparseSetStorage::<usize>::new();
+
+        assert!(storage.is_empty());
+    }
+
+    #[test]
+    fn test_sparse_set_insert_get() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = SparseSetStorage::<usize>::new();
+        let entity0 = allocator.allocate();
+        let entity1 = allocator.allocate();
+        storage.insert(entity1, 1);
+
+        assert!(!storage.contains(entity0));
+        assert_eq!(storage.get(entity0), None);
+        assert!(storage.contains(entity1));
+        assert_eq!(storage.get(entity1), Some(&1));
+    }
+
+    #[test]
+    fn test_sparse_set_insert_replaces_existing() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = SparseSetStorage::<usize>::new();
+        let entity = allocator.allocate();
+
+        assert_eq!(storage.insert(entity, 1), None);
+        assert_eq!(storage.insert(entity, 2), Some(1));
+        assert_eq!(storage.get(entity), Some(&2));
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn test_sparse_set_remove_keeps_remaining_entities() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = SparseSetStorage::<usize>::new();
+        let entities: Vec<_> = allocator.by_ref().take(5).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            storage.insert(*entity, i);
+        }
+
+        assert_eq!(storage.remove(entities[1]), Some(1));
+        assert_eq!(storage.remove(entities[1]), None);
+        assert_eq!(storage.len(), 4);
+        for (i, entity) in entities.iter().enumerate().filter(|(i, _)| *i != 1) {
+            assert_eq!(storage.get(*entity), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_sparse_set_join() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = SparseSetStorage::<usize>::new();
+        let entities: Vec<_> = allocator.by_ref().take(5).collect();
+        storage.insert(entities[3], 3);
+        storage.insert(entities[0], 0);
+        let result: Vec<_> = storage.join(&entities).collect();
+        let expected = vec![(ComponentIndex::new(0), &0), (ComponentIndex::new(3), &3)];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_sparse_set_index_follows_live_entities() {
+        let mut allocator = EntityAllocator::new();
+        let mut storage = SparseSetStorage::<usize>::new();
+        let entities: Vec<_> = allocator.by_ref().take(10_000).collect();
+        storage.insert(entities[9_999], 0);
+
+        assert_eq!(storage.sparse.len(), 1);
+
+        storage.remove(entities[9_999]);
+        storage.shrink_to_fit();
+
+        assert_eq!(storage.sparse.capacity(), 0);
+    }
+}
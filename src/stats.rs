@@ -0,0 +1,232 @@
+use crate::component::{
+    ComponentTypeIndex,
+};
+use crate::storage::{
+    ComponentMetadata,
+    EntityTypeIndex,
+};
+use std::ops;
+
+
+/// The memory used by a single allocation of homogeneous elements.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    length: usize,
+    capacity: usize,
+    capacity_bytes: usize,
+    wasted_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub(crate) fn new(element_size: usize, length: usize, capacity: usize) -> MemoryUsage {
+        // Zero sized types report an unbounded capacity, but they never allocate.
+        let capacity = if element_size == 0 { length } else { capacity };
+
+        MemoryUsage {
+            length,
+            capacity,
+            capacity_bytes: element_size * capacity,
+            wasted_bytes: element_size * (capacity - length),
+        }
+    }
+
+    /// The number of elements stored in the allocation.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The number of elements the allocation can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The size of the allocation in bytes.
+    pub fn capacity_bytes(&self) -> usize {
+        self.capacity_bytes
+    }
+
+    /// The number of bytes in the allocation not occupied by elements.
+    pub fn wasted_bytes(&self) -> usize {
+        self.wasted_bytes
+    }
+}
+
+impl ops::Add for MemoryUsage {
+    type Output = MemoryUsage;
+
+    fn add(self, other: MemoryUsage) -> Self::Output {
+        MemoryUsage {
+            length: self.length + other.length,
+            capacity: self.capacity + other.capacity,
+            capacity_bytes: self.capacity_bytes + other.capacity_bytes,
+            wasted_bytes: self.wasted_bytes + other.wasted_bytes,
+        }
+    }
+}
+
+impl ops::AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: MemoryUsage) {
+        *self = *self + other;
+    }
+}
+
+/// The memory used by the components of one component type.
+#[derive(Clone, Debug)]
+pub struct ComponentMemoryStats {
+    component_type: ComponentTypeIndex,
+    name: &'static str,
+    usage: MemoryUsage,
+}
+
+impl ComponentMemoryStats {
+    pub(crate) fn new(component_type: ComponentTypeIndex, metadata: &ComponentMetadata) -> Self {
+        Self {
+            component_type,
+            name: metadata.name(),
+            usage: MemoryUsage::default(),
+        }
+    }
+
+    pub fn component_type(&self) -> ComponentTypeIndex {
+        self.component_type
+    }
+
+    /// The type name of the component.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    pub(crate) fn add_usage(&mut self, usage: MemoryUsage) {
+        self.usage += usage;
+    }
+}
+
+/// The memory used by one entity type: its entity list and its slice in each
+/// component storage.
+#[derive(Clone, Debug)]
+pub struct EntityTypeMemoryStats {
+    index: EntityTypeIndex,
+    entities: MemoryUsage,
+    components: Vec<ComponentMemoryStats>,
+}
+
+impl EntityTypeMemoryStats {
+    pub(crate) fn new(index: EntityTypeIndex, entities: MemoryUsage) -> Self {
+        Self {
+            index,
+            entities,
+            components: Vec::new(),
+        }
+    }
+
+    pub fn index(&self) -> EntityTypeIndex {
+        self.index
+    }
+
+    pub fn entity_count(&self) -> usize {
+        self.entities.length()
+    }
+
+    /// The memory used by the list of entities in the entity type.
+    pub fn entities(&self) -> MemoryUsage {
+        self.entities
+    }
+
+    /// The memory used by the entity type's slice in each component storage.
+    pub fn components(&self) -> &[ComponentMemoryStats] {
+        &self.components
+    }
+
+    /// The memory used by the entity list and all component slices together.
+    pub fn total(&self) -> MemoryUsage {
+        self.components
+            .iter()
+            .fold(self.entities, |total, component| total + component.usage())
+    }
+
+    pub(crate) fn push(&mut self, component: ComponentMemoryStats) {
+        self.components.push(component);
+    }
+}
+
+/// A snapshot of the memory used by a world.
+#[derive(Clone, Debug)]
+pub struct MemoryStats {
+    entity_types: Vec<EntityTypeMemoryStats>,
+    components: Vec<ComponentMemoryStats>,
+    sparse_components: Vec<ComponentMemoryStats>,
+}
+
+impl MemoryStats {
+    pub(crate) fn new(
+        entity_types: Vec<EntityTypeMemoryStats>,
+        mut components: Vec<ComponentMemoryStats>,
+        mut sparse_components: Vec<ComponentMemoryStats>,
+    ) -> Self
+    {
+        components.sort_by(|a, b| a.name().cmp(b.name()));
+        sparse_components.sort_by(|a, b| a.name().cmp(b.name()));
+
+        Self {
+            entity_types,
+            components,
+            sparse_components,
+        }
+    }
+
+    /// The memory used by each entity type, in entity type order.
+    pub fn entity_types(&self) -> &[EntityTypeMemoryStats] {
+        &self.entity_types
+    }
+
+    /// The memory used by each component storage, summed over all entity types.
+    pub fn components(&self) -> &[ComponentMemoryStats] {
+        &self.components
+    }
+
+    /// The memory used by the components of each sparse set.
+    pub fn sparse_components(&self) -> &[ComponentMemoryStats] {
+        &self.sparse_components
+    }
+
+    /// The memory used by the whole world.
+    pub fn total(&self) -> MemoryUsage {
+        let entity_types = self.entity_types
+            .iter()
+            .fold(MemoryUsage::default(), |total, entity_type| total + entity_type.total());
+
+        self.sparse_components
+            .iter()
+            .fold(entity_types, |total, component| total + component.usage())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MemoryUsage,
+    };
+
+
+    #[test]
+    fn test_memory_usage() {
+        let usage = MemoryUsage::new(8, 3, 4);
+
+        assert_eq!(usage.capacity_bytes(), 32);
+        assert_eq!(usage.wasted_bytes(), 8);
+    }
+
+    #[test]
+    fn test_memory_usage_zero_sized_type() {
+        let usage = MemoryUsage::new(0, 3, usize::MAX);
+
+        assert_eq!(usage.capacity(), 3);
+        assert_eq!(usage.capacity_bytes(), 0);
+        assert_eq!(usage.wasted_bytes(), 0);
+    }
+}
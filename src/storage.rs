@@ -5,6 +5,9 @@ use crate::component::{
     Component,
     ComponentTypeIndex,
 };
+use crate::allocator::{
+    ComponentAllocator,
+};
 use downcast::{
     Downcast,
     impl_downcast,
@@ -15,12 +18,13 @@ use std::sync::{
 use std::collections::{
     HashMap,
 };
+use std::any;
 use std::mem;
 use std::ops;
 use std::ptr;
 
 
-/// The components in an entity, along with the constructors to contruct another instance of 
+/// The components in an entity, along with the constructors to contruct another instance of
 /// and entity kind.
 #[derive(Clone, Default, Debug)]
 pub struct EntityLayout {
@@ -47,7 +51,7 @@ impl EntityLayout {
     }
 
     pub fn get_constructor_unchecked(
-        &self, 
+        &self,
         index: ComponentTypeIndex
     ) -> &fn() -> Box<dyn OpaqueComponentStorage>
     {
@@ -71,11 +75,24 @@ impl EntityLayout {
         let type_id = ComponentTypeIndex::of::<T>();
         self.components.contains(&type_id)
     }
+
+    /// The layout with every component type of this layout but `type_id`.
+    pub(crate) fn without_component(&self, type_id: ComponentTypeIndex) -> EntityLayout {
+        let mut layout = EntityLayout::new();
+        for (component, constructor) in self.components.iter().zip(self.constructors.iter()) {
+            if *component != type_id {
+                layout.components.push(*component);
+                layout.constructors.push(*constructor);
+            }
+        }
+
+        layout
+    }
 }
 
 /// A collection of entities with the same layout. We create a new map every time
 /// a new entity layout is registered.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct EntityType {
     index: EntityTypeIndex,
     entities: Vec<Entity>,
@@ -85,7 +102,7 @@ pub struct EntityType {
 impl EntityType {
     pub(crate) fn new(index: EntityTypeIndex, layout: EntityLayout) -> Self {
         Self {
-            index: index,
+            index,
             entities: Vec::new(),
             layout: Arc::new(layout),
         }
@@ -96,9 +113,14 @@ impl EntityType {
     }
 
     pub(crate) fn swap_remove(&mut self, entity_index: usize) -> Entity {
-        let removed = self.entities.swap_remove(entity_index);
+        self.entities.swap_remove(entity_index)
+    }
 
-        removed
+    /// Remove the entities whose flag in `removed` is set, keeping the order of
+    /// the remaining entities.
+    pub(crate) fn remove_marked(&mut self, removed: &[bool]) {
+        let mut removed = removed.iter();
+        self.entities.retain(|_| !*removed.next().unwrap());
     }
 
     pub(crate) fn index(&self) -> EntityTypeIndex {
@@ -120,6 +142,41 @@ impl EntityType {
     pub(crate) fn push(&mut self, entity: Entity) {
         self.entities.push(entity);
     }
+
+    /// Remove every entity from the entity type, keeping its capacity.
+    pub(crate) fn clear(&mut self) {
+        self.entities.clear();
+    }
+
+    /// Remove every entity from the entity type, leaving its capacity untouched.
+    pub(crate) fn take_entities(&mut self) -> Vec<Entity> {
+        self.entities.drain(..).collect()
+    }
+
+    /// The fraction of the entity type's capacity that is occupied by entities.
+    pub fn occupancy(&self) -> f32 {
+        if self.entities.capacity() == 0 {
+            1_f32
+        } else {
+            (self.entities.len() as f32) / (self.entities.capacity() as f32)
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.entities.shrink_to_fit();
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.entities.capacity()
+    }
+
+    pub(crate) fn set_index(&mut self, index: EntityTypeIndex) {
+        self.index = index;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
 }
 
 impl ops::Index<EntityTypeIndex> for Vec<EntityType> {
@@ -145,7 +202,7 @@ impl EntityTypeIndex {
     #[inline]
     pub(crate) fn new(id: usize) -> EntityTypeIndex {
         EntityTypeIndex {
-            id: id,
+            id,
         }
     }
 
@@ -165,7 +222,7 @@ impl ComponentIndex {
     #[inline]
     pub fn new(id: usize) -> ComponentIndex {
         ComponentIndex {
-            id: id,
+            id,
         }
     }
 
@@ -178,7 +235,7 @@ impl ComponentIndex {
 /// The location of an entity and its components.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EntityLocation {
-    type_id: EntityTypeIndex, 
+    type_id: EntityTypeIndex,
     component_id: ComponentIndex,
 }
 
@@ -186,8 +243,8 @@ impl EntityLocation {
     #[inline]
     pub(crate) fn new(type_id: EntityTypeIndex, component_id: ComponentIndex) -> Self {
         Self {
-            type_id: type_id,
-            component_id: component_id,
+            type_id,
+            component_id,
         }
     }
 
@@ -216,16 +273,16 @@ impl EntityLocationMap {
     }
 
     pub(crate) fn insert(
-        &mut self, 
+        &mut self,
         entities: &[Entity],
-        entity_type: EntityTypeIndex, 
+        entity_type: EntityTypeIndex,
         base: ComponentIndex
     ) -> Vec<EntityLocation>
     {
         let mut removed_entities = Vec::new();
         for (i, entity) in entities.iter().enumerate() {
             let location = EntityLocation::new(
-                entity_type, 
+                entity_type,
                 ComponentIndex::new(base.id() + i)
             );
             if let Some(existing_location) = self.locations.insert(*entity, location) {
@@ -248,12 +305,8 @@ impl EntityLocationMap {
         self.locations.contains_key(&entity)
     }
 
-    pub(crate) fn iter(&self) -> impl Iterator<Item = &Entity> {
-        self.locations.keys()
-    }
-
     pub fn get(&self, entity: Entity) -> Option<EntityLocation> {
-        self.locations.get(&entity).map(|idx| *idx)
+        self.locations.get(&entity).copied()
     }
 
     pub fn set(&mut self, entity: Entity, location: EntityLocation) -> Option<EntityLocation> {
@@ -263,21 +316,49 @@ impl EntityLocationMap {
     pub fn remove(&mut self, entity: Entity) -> Option<EntityLocation> {
         self.locations.remove(&entity)
     }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.locations.shrink_to_fit();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.locations.clear();
+    }
+
+    /// Update the entity type of every location after entity types have been
+    /// removed from a world.
+    pub(crate) fn remap_entity_types(&mut self, remap: &[Option<EntityTypeIndex>]) {
+        for location in self.locations.values_mut() {
+            let new_index = remap[location.entity_type().id()]
+                .expect("An entity is located in a removed entity type");
+            *location = EntityLocation::new(new_index, location.component());
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ComponentView<'a, T> {
     slice: &'a [T],
+    alignment: usize,
 }
 
 impl<'a, T> ComponentView<'a, T>{
     #[inline]
-    pub (crate) fn new(slice: &'a [T]) -> ComponentView<'a, T> {
+    pub (crate) fn new(slice: &'a [T], alignment: usize) -> ComponentView<'a, T> {
+        debug_assert_eq!(slice.as_ptr() as usize % alignment, 0);
         ComponentView {
-            slice: slice,
+            slice,
+            alignment,
         }
     }
 
+    /// The alignment in bytes guaranteed for the start of the slice. This is at
+    /// least the alignment of `T`, and more if the storage is over-aligned.
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
     #[inline]
     pub fn into_slice(self) -> &'a [T] {
         self.slice
@@ -286,7 +367,7 @@ impl<'a, T> ComponentView<'a, T>{
 
 impl<'a, T: Component> Clone for ComponentView<'a, T> {
     fn clone(&self) -> Self {
-        ComponentView::new(self.slice)
+        ComponentView::new(self.slice, self.alignment)
     }
 }
 
@@ -312,28 +393,44 @@ impl<'a, T> ops::Index<ComponentIndex> for ComponentView<'a, T> {
     }
 }
 
+/// A mutable view of the components of an entity type. A mutable view is the
+/// only reference to its components, so it cannot be cloned; reborrow it with
+/// [`ComponentViewMut::reborrow`] instead.
 #[derive(Debug)]
 pub struct ComponentViewMut<'a, T> {
     slice: &'a mut [T],
+    alignment: usize,
 }
 
 impl<'a, T> ComponentViewMut<'a, T>{
     #[inline]
-    pub(crate) fn new(slice: &'a mut [T]) -> ComponentViewMut<'a, T> {
+    pub(crate) fn new(slice: &'a mut [T], alignment: usize) -> ComponentViewMut<'a, T> {
+        debug_assert_eq!(slice.as_ptr() as usize % alignment, 0);
         ComponentViewMut {
-            slice: slice,
+            slice,
+            alignment,
         }
     }
 
+    /// The alignment in bytes guaranteed for the start of the slice.
     #[inline]
-    pub fn into_slice(self) -> &'a mut [T] {
-        self.slice
+    pub fn alignment(&self) -> usize {
+        self.alignment
     }
-}
 
-impl<'a, T: Component> Clone for ComponentViewMut<'a, T> {
-    fn clone(&self) -> Self {
-        todo!("IMPLEMENT ME!")
+    /// Borrow the view for a shorter lifetime, leaving the view itself unusable
+    /// until the new one is dropped.
+    #[inline]
+    pub fn reborrow(&mut self) -> ComponentViewMut<'_, T> {
+        ComponentViewMut {
+            slice: self.slice,
+            alignment: self.alignment,
+        }
+    }
+
+    #[inline]
+    pub fn into_slice(self) -> &'a mut [T] {
+        self.slice
     }
 }
 
@@ -341,7 +438,13 @@ impl<'a, T: Component> ops::Deref for ComponentViewMut<'a, T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        &self.slice
+        self.slice
+    }
+}
+
+impl<'a, T: Component> ops::DerefMut for ComponentViewMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.slice
     }
 }
 
@@ -359,8 +462,9 @@ impl<'a, T> ops::IndexMut<ComponentIndex> for ComponentViewMut<'a, T> {
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone)]
 pub struct ComponentMetadata {
+    name: &'static str,
     size: usize,
     alignment: usize,
     drop_fn: Option<fn(*mut u8)>,
@@ -377,12 +481,18 @@ impl ComponentMetadata {
         };
 
         ComponentMetadata {
+            name: any::type_name::<T>(),
             size: mem::size_of::<T>(),
             alignment: mem::align_of::<T>(),
-            drop_fn: drop_fn,
+            drop_fn,
         }
     }
 
+    /// The type name of the component, for diagnostic purposes.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
     pub fn size(&self) -> usize {
         self.size
     }
@@ -395,6 +505,12 @@ impl ComponentMetadata {
         self.alignment - self.size
     }
 
+    /// Drop the component value pointed to by `value` in place.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to a valid, initialized value of the component type
+    /// this metadata was constructed from. The value must not be used afterwards.
     pub unsafe fn drop(&self, value: *mut u8) {
         if let Some(drop_fn) = self.drop_fn {
             drop_fn(value)
@@ -402,23 +518,80 @@ impl ComponentMetadata {
     }
 }
 
+// Function pointers are not guaranteed to be unique, so two metadata of the
+// same type may hold different drop functions. Compare whether the type needs
+// dropping instead.
+impl PartialEq for ComponentMetadata {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.size == other.size
+            && self.alignment == other.alignment
+            && self.drop_fn.is_some() == other.drop_fn.is_some()
+    }
+}
+
 pub trait OpaqueComponentStorage: Downcast + Send + Sync {
     fn metadata(&self) -> ComponentMetadata;
 
+    /// The type of the components in the storage.
+    fn component_type(&self) -> ComponentTypeIndex;
+
+    /// Set the allocator for the component arrays the storage creates from now
+    /// on. Existing arrays keep releasing their memory to the allocator they
+    /// were allocated with.
+    fn set_allocator(&mut self, allocator: Arc<dyn ComponentAllocator>);
+
+    /// Construct an empty storage of the same type, with the same allocator,
+    /// alignment and other configuration as this storage.
+    fn new_empty(&self) -> Box<dyn OpaqueComponentStorage>;
+
+    /// The alignment in bytes of the component arrays in the storage.
+    fn alignment(&self) -> usize;
+
+    /// Over-align the component arrays of the storage to at least `alignment`
+    /// bytes, which must be a power of two. The alignment can only be changed
+    /// while the storage has no entity types, and this returns `false` otherwise.
+    fn set_alignment(&mut self, alignment: usize) -> bool;
+
     fn swap_remove(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex);
 
+    /// Remove a component like [`OpaqueComponentStorage::swap_remove`], moving
+    /// it to `dst` instead of dropping it. `dst` must be valid for writing one
+    /// component of the storage's type.
+    unsafe fn swap_remove_raw(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex, dst: *mut u8);
+
+    /// Drop the components of an entity type whose flag in `removed` is set, and
+    /// move the remaining components down in a single pass, keeping their order.
+    /// `removed` holds one flag per component of the entity type.
+    fn remove_marked(&mut self, entity_type: EntityTypeIndex, removed: &[bool]);
+
+    /// Return the number of components of an entity type in the storage.
+    fn count(&self, entity_type: EntityTypeIndex) -> Option<usize>;
+
+    /// Return the number of components of an entity type, starting at `index`,
+    /// that the storage holds contiguously. This is at least one for every
+    /// component of the entity type.
+    fn run_len(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> usize;
+
+    /// Return the components of an entity type as one contiguous slice. Storages
+    /// that do not store an entity type's components contiguously return `None`.
     fn get_bytes(&self, entity_type: EntityTypeIndex) -> Option<(*const u8, usize)>;
 
     unsafe fn get_bytes_mut(&mut self, entity_type: EntityTypeIndex) -> Option<(*mut u8, usize)>;
 
+    /// Return a pointer to a single component of an entity type.
+    fn get_component_ptr(&self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*const u8>;
+
+    unsafe fn get_component_ptr_mut(&mut self, entity_type: EntityTypeIndex, index: ComponentIndex) -> Option<*mut u8>;
+
     unsafe fn extend_memcopy_raw(&mut self, entity_type: EntityTypeIndex, ptr: *const u8, count: usize);
 
     /// Move all the components of a given entity type from one storage to the
     /// other storage.
     fn transfer_entity_type(
-        &mut self, 
-        src: EntityTypeIndex, 
-        dst: EntityTypeIndex, 
+        &mut self,
+        src: EntityTypeIndex,
+        dst: EntityTypeIndex,
         dst_storage: &mut dyn OpaqueComponentStorage,
     );
 
@@ -441,6 +614,23 @@ pub trait OpaqueComponentStorage: Downcast + Send + Sync {
 
     /// Create a new slice for the given Entity type.
     fn insert_entity_type(&mut self, entity_type: EntityTypeIndex);
+
+    /// Reorganize the storage after entity types have been removed from a world.
+    /// The remapping maps the index of each old entity type to its new index, or
+    /// to `None` if the entity type was removed. The slices of removed entity
+    /// types are dropped.
+    fn remap_entity_types(&mut self, remap: &[Option<EntityTypeIndex>]);
+
+    /// Return the number of components the slice for an entity type can hold
+    /// without reallocating.
+    fn capacity(&self, entity_type: EntityTypeIndex) -> Option<usize>;
+
+    /// Release the excess capacity of the slice for an entity type.
+    fn shrink_to_fit(&mut self, entity_type: EntityTypeIndex);
+
+    /// Drop every component in the storage, keeping the slice of each entity
+    /// type along with its capacity.
+    fn clear(&mut self);
 }
 
 impl_downcast!(OpaqueComponentStorage);
@@ -452,13 +642,19 @@ pub trait ComponentStorage<'a, T: Component>: OpaqueComponentStorage + Default {
 
     fn get(&'a self, entity_type: EntityTypeIndex) -> Option<ComponentView<'a, T>>;
 
-    fn get_mut(&'a self, entity_type: EntityTypeIndex) -> Option<ComponentViewMut<'a, T>>;
+    /// Get a mutable view of the components of an entity type. This borrows the
+    /// storage exclusively, so no two views of the storage can alias.
+    fn get_mut(&'a mut self, entity_type: EntityTypeIndex) -> Option<ComponentViewMut<'a, T>>;
+
+    /// Get mutable views of several entity types at once, in the order of
+    /// `entity_types`. Panics if an entity type is listed twice.
+    fn get_disjoint_mut(&'a mut self, entity_types: &[EntityTypeIndex]) -> Vec<Option<ComponentViewMut<'a, T>>>;
 
     unsafe fn extend_memcopy(&mut self, entity_type: EntityTypeIndex, ptr: *const T, len: usize);
 
     fn iter(&'a self) -> Self::Iter;
 
-    fn iter_mut(&'a self) -> Self::IterMut;
+    fn iter_mut(&'a mut self) -> Self::IterMut;
 
     /// Return the number of entity type views in the storage.
     fn len(&self) -> usize;
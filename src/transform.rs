@@ -0,0 +1,232 @@
+use crate::entity::{
+    Entity,
+};
+use crate::world::{
+    World,
+};
+use std::ops;
+
+
+/// A 4x4 affine transformation matrix, stored in row major order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    rows: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        rows: [
+            [1_f32, 0_f32, 0_f32, 0_f32],
+            [0_f32, 1_f32, 0_f32, 0_f32],
+            [0_f32, 0_f32, 1_f32, 0_f32],
+            [0_f32, 0_f32, 0_f32, 1_f32],
+        ],
+    };
+
+    pub fn from_rows(rows: [[f32; 4]; 4]) -> Matrix4 {
+        Matrix4 {
+            rows,
+        }
+    }
+
+    pub fn rows(&self) -> &[[f32; 4]; 4] {
+        &self.rows
+    }
+
+    /// Apply the transformation to a point.
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let mut result = [0_f32; 3];
+        for (i, value) in result.iter_mut().enumerate() {
+            let row = &self.rows[i];
+            *value = row[0] * point[0] + row[1] * point[1] + row[2] * point[2] + row[3];
+        }
+
+        result
+    }
+}
+
+impl ops::Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Self::Output {
+        let mut rows = [[0_f32; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+
+        Matrix4::from_rows(rows)
+    }
+}
+
+/// The transform of an entity relative to its parent, or to the world if it
+/// has no parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LocalTransform {
+    pub translation: [f32; 3],
+    /// A unit quaternion, as `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl LocalTransform {
+    pub const IDENTITY: LocalTransform = LocalTransform {
+        translation: [0_f32; 3],
+        rotation: [0_f32, 0_f32, 0_f32, 1_f32],
+        scale: [1_f32; 3],
+    };
+
+    pub fn from_translation(translation: [f32; 3]) -> LocalTransform {
+        LocalTransform {
+            translation,
+            ..LocalTransform::IDENTITY
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: [f32; 4]) -> LocalTransform {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: [f32; 3]) -> LocalTransform {
+        self.scale = scale;
+        self
+    }
+
+    /// The matrix scaling, then rotating, then translating a point.
+    pub fn compute_matrix(&self) -> Matrix4 {
+        let [x, y, z, w] = self.rotation;
+        let [sx, sy, sz] = self.scale;
+        let [tx, ty, tz] = self.translation;
+        let rotation = [
+            [1_f32 - 2_f32 * (y * y + z * z), 2_f32 * (x * y - w * z), 2_f32 * (x * z + w * y)],
+            [2_f32 * (x * y + w * z), 1_f32 - 2_f32 * (x * x + z * z), 2_f32 * (y * z - w * x)],
+            [2_f32 * (x * z - w * y), 2_f32 * (y * z + w * x), 1_f32 - 2_f32 * (x * x + y * y)],
+        ];
+
+        Matrix4::from_rows([
+            [rotation[0][0] * sx, rotation[0][1] * sy, rotation[0][2] * sz, tx],
+            [rotation[1][0] * sx, rotation[1][1] * sy, rotation[1][2] * sz, ty],
+            [rotation[2][0] * sx, rotation[2][1] * sy, rotation[2][2] * sz, tz],
+            [0_f32, 0_f32, 0_f32, 1_f32],
+        ])
+    }
+}
+
+impl Default for LocalTransform {
+    fn default() -> LocalTransform {
+        LocalTransform::IDENTITY
+    }
+}
+
+/// The transform of an entity relative to the world, computed from the local
+/// transforms of the entity and its ancestors by [`propagate_transforms`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlobalTransform {
+    matrix: Matrix4,
+}
+
+impl GlobalTransform {
+    pub const IDENTITY: GlobalTransform = GlobalTransform {
+        matrix: Matrix4::IDENTITY,
+    };
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+
+    pub fn translation(&self) -> [f32; 3] {
+        let rows = self.matrix.rows();
+
+        [rows[0][3], rows[1][3], rows[2][3]]
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        self.matrix.transform_point(point)
+    }
+}
+
+impl Default for GlobalTransform {
+    fn default() -> GlobalTransform {
+        GlobalTransform::IDENTITY
+    }
+}
+
+/// Recompute the global transforms of every entity with a local transform.
+///
+/// The hierarchy is walked depth first from each root, entity by entity, so every
+/// parent is updated before its children. The roots are the entities with a
+/// local transform whose parent, if any, has none. Entities without a global
+/// transform are walked, but only their descendants are updated, and the walk
+/// stops at entities without a local transform.
+///
+/// Every root and every walked entity costs a lookup of its location, and the
+/// components are read and written through it one entity at a time rather than
+/// column by column. There is no change detection yet, so every subtree is
+/// recomputed on each call, whether or not any of its transforms changed.
+pub fn propagate_transforms(world: &mut World) {
+    let roots: Vec<Entity> = world.entity_types()
+        .iter()
+        .filter(|entity_type| entity_type.contains_component::<LocalTransform>())
+        .flat_map(|entity_type| entity_type.entities().iter().copied())
+        .filter(|entity| {
+            world.parent(*entity).is_none_or(|parent| !world.has_component::<LocalTransform>(parent))
+        })
+        .collect();
+
+    let mut stack: Vec<(Entity, Matrix4)> = roots
+        .into_iter()
+        .rev()
+        .map(|root| (root, Matrix4::IDENTITY))
+        .collect();
+    while let Some((entity, parent_matrix)) = stack.pop() {
+        let mut entry = match world.entry(entity) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let local = match entry.get_component::<LocalTransform>() {
+            Ok(local) => *local,
+            Err(_) => continue,
+        };
+        let matrix = parent_matrix * local.compute_matrix();
+        if let Ok(global) = entry.get_component_mut::<GlobalTransform>() {
+            global.matrix = matrix;
+        }
+
+        for child in world.children(entity).iter().rev() {
+            stack.push((*child, matrix));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        LocalTransform,
+        Matrix4,
+    };
+
+
+    #[test]
+    fn test_compute_matrix_scales_rotates_translates() {
+        // A quarter turn around the z axis.
+        let half_sqrt = std::f32::consts::FRAC_1_SQRT_2;
+        let transform = LocalTransform::from_translation([1_f32, 0_f32, 0_f32])
+            .with_rotation([0_f32, 0_f32, half_sqrt, half_sqrt])
+            .with_scale([2_f32, 2_f32, 2_f32]);
+        let point = transform.compute_matrix().transform_point([1_f32, 0_f32, 0_f32]);
+
+        assert!((point[0] - 1_f32).abs() < 1e-6);
+        assert!((point[1] - 2_f32).abs() < 1e-6);
+        assert!(point[2].abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_identity_matrix() {
+        let transform = LocalTransform::default();
+
+        assert_eq!(transform.compute_matrix(), Matrix4::IDENTITY);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::allocator::{
+    ComponentAllocator,
+    GlobalAllocator,
+};
+use crate::entity::{
+    EntityAllocator,
+    EntityIdSource,
+};
+use crate::world::{
+    World,
+};
+use std::sync::{
+    Arc,
+};
+
+
+/// The number of entity ids a world takes from its universe at a time.
+pub const DEFAULT_ENTITY_BLOCK_SIZE: u64 = 1024;
+
+/// A set of worlds sharing one entity id space.
+///
+/// Every world created by a universe allocates its entity ids from blocks taken
+/// from the universe's shared id source, so no two worlds of a universe ever hand
+/// out the same id. Entities merged between worlds of the same universe keep 
+/// their ids. A universe is cheap to clone, and the clones share the id space.
+#[derive(Clone, Debug)]
+pub struct Universe {
+    ids: Arc<EntityIdSource>,
+}
+
+impl Universe {
+    pub fn new() -> Universe {
+        Universe::with_block_size(DEFAULT_ENTITY_BLOCK_SIZE)
+    }
+
+    /// Construct a universe whose worlds take `block_size` entity ids at a time.
+    pub fn with_block_size(block_size: u64) -> Universe {
+        Universe {
+            ids: Arc::new(EntityIdSource::new(block_size)),
+        }
+    }
+
+    /// Construct an entity allocator that allocates ids in the universe's id space.
+    pub fn entity_allocator(&self) -> EntityAllocator {
+        EntityAllocator::with_source(self.ids.clone())
+    }
+
+    pub fn create_world(&self) -> World {
+        self.create_world_with_allocator(GlobalAllocator::shared())
+    }
+
+    /// Construct a world in the universe whose component storages allocate their
+    /// component arrays from `allocator`.
+    pub fn create_world_with_allocator(&self, allocator: Arc<dyn ComponentAllocator>) -> World {
+        World::with_allocators(self.entity_allocator(), allocator)
+    }
+}
+
+impl Default for Universe {
+    fn default() -> Universe {
+        Universe::new()
+    }
+}
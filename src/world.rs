@@ -1,7 +1,17 @@
+use crate::allocator::{
+    ComponentAllocator,
+    GlobalAllocator,
+};
 use crate::component::{
     Component,
     ComponentTypeIndex,
 };
+use crate::cell::{
+    BorrowError,
+    StorageCell,
+    StorageRef,
+    StorageRefMut,
+};
 use crate::compactable::{
     CompactableStorage,
 };
@@ -9,6 +19,34 @@ use crate::entity::{
     Entity,
     EntityAllocator,
 };
+use crate::entry::{
+    Entry,
+};
+use crate::stats::{
+    ComponentMemoryStats,
+    EntityTypeMemoryStats,
+    MemoryStats,
+    MemoryUsage,
+};
+use crate::sparse::{
+    OpaqueSparseStorage,
+    SparseSetStorage,
+};
+use crate::hierarchy::{
+    Children,
+    Parent,
+};
+use crate::prefab::{
+    Prefab,
+};
+use crate::relation::{
+    RelationMap,
+};
+use crate::registry::{
+    CloneFns,
+    ComponentRegistry,
+    MapEntities,
+};
 use crate::storage::{
     OpaqueComponentStorage,
     EntityLocationMap,
@@ -17,25 +55,34 @@ use crate::storage::{
     EntityTypeIndex,
     EntityLocation,
     StoreComponentsIn,
-    ComponentStorage,
     ComponentIndex,
 };
-use downcast::{
-    Downcast,
-};
 use std::collections::{
     HashMap,
-    HashSet,
 };
+use std::any;
+use std::cmp;
+use std::error;
+use std::fmt;
 use std::mem;
-use std::ops::{
-    DerefMut,
+use std::ptr;
+use std::sync::{
+    Arc,
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
 };
 
 
 /// where the components live in a world.
+///
+/// Every storage carries its own borrow flag, so storages can be borrowed
+/// through a shared reference to the map: any number of shared borrows of a
+/// storage, or a single mutable one, at a time. Borrowing through an exclusive
+/// reference to the map needs no flags at all.
 pub struct ComponentMap {
-    data: HashMap<ComponentTypeIndex, Box<dyn OpaqueComponentStorage>>,
+    data: HashMap<ComponentTypeIndex, StorageCell>,
 }
 
 impl ComponentMap {
@@ -55,25 +102,96 @@ impl ComponentMap {
     {
         let new_storage = self.data
             .entry(index)
-            .or_insert_with(constructor);
-        
-        new_storage.deref_mut()
+            .or_insert_with(|| StorageCell::new(constructor()));
+
+        new_storage.get_mut()
     }
 
-    fn get(&self, component_type: ComponentTypeIndex) -> Option<&dyn OpaqueComponentStorage> {
-        self.data.get(&component_type).map(|cell| cell.as_ref())
+    /// Borrow the storage of a component type, panicking if it is borrowed
+    /// mutably.
+    fn get(&self, component_type: ComponentTypeIndex) -> Option<StorageRef<'_, dyn OpaqueComponentStorage>> {
+        self.data.get(&component_type).map(|cell| match cell.try_borrow() {
+            Some(storage) => storage,
+            None => panic!("The storage of a component type is already borrowed mutably"),
+        })
     }
 
     fn get_mut(&mut self, component_type: ComponentTypeIndex) -> Option<&mut dyn OpaqueComponentStorage> {
         self.data
             .get_mut(&component_type)
-            .map(|cell| cell.as_mut())
+            .map(|cell| cell.get_mut())
     }
 
-    pub fn get_view<T: Component + StoreComponentsIn>(&self) -> Option<&T::Storage> {
+    /// Register a component storage to use for its component type instead of
+    /// the component's default storage. Returns `false` if the component type
+    /// already has a storage.
+    fn insert(&mut self, storage: Box<dyn OpaqueComponentStorage>) -> bool {
+        let component_type = storage.component_type();
+        if self.data.contains_key(&component_type) {
+            return false;
+        }
+
+        self.data.insert(component_type, StorageCell::new(storage));
+
+        true
+    }
+
+    /// Get a single component of an entity type, independent of the kind of
+    /// storage the component type lives in. The storage stays borrowed as long
+    /// as the component. Panics if the storage is borrowed mutably.
+    pub fn get_component<T: Component>(
+        &self,
+        entity_type: EntityTypeIndex,
+        index: ComponentIndex
+    ) -> Option<StorageRef<'_, T>>
+    {
         let component_type = ComponentTypeIndex::of::<T>();
-        self.get(component_type)
-            .and_then(|storage| storage.downcast_ref())
+        self.get(component_type).and_then(|storage| {
+            StorageRef::filter_map(storage, |storage| {
+                storage.get_component_ptr(entity_type, index).map(|ptr| unsafe {
+                    // The storage for a component type only holds values of that type.
+                    &*(ptr as *const T)
+                })
+            })
+        })
+    }
+
+    /// Get a single component of an entity type without borrowing its storage.
+    ///
+    /// # Safety
+    ///
+    /// The storage of `T` must not be borrowed mutably while the reference is
+    /// alive, which holds as long as the map is borrowed exclusively.
+    pub(crate) unsafe fn get_component_unchecked<T: Component>(
+        &self,
+        entity_type: EntityTypeIndex,
+        index: ComponentIndex
+    ) -> Option<&T>
+    {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data.get(&component_type)
+            .and_then(|cell| cell.get_unchecked().get_component_ptr(entity_type, index))
+            .map(|ptr| &*(ptr as *const T))
+    }
+
+    pub fn get_component_mut<T: Component>(
+        &mut self,
+        entity_type: EntityTypeIndex,
+        index: ComponentIndex
+    ) -> Option<&mut T>
+    {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.get_mut(component_type)
+            .and_then(|storage| unsafe { storage.get_component_ptr_mut(entity_type, index) })
+            .map(|ptr| unsafe {
+                &mut *(ptr as *mut T)
+            })
+    }
+
+    /// Borrow the storage of the component type `T`. Panics if the storage is
+    /// borrowed mutably.
+    pub fn get_view<T: Component + StoreComponentsIn>(&self) -> Option<StorageRef<'_, T::Storage>> {
+        self.get_storage::<T, T::Storage>()
     }
 
     pub fn get_view_mut<T: Component + StoreComponentsIn>(&mut self) -> Option<&mut T::Storage> {
@@ -82,94 +200,317 @@ impl ComponentMap {
             .and_then(|storage| storage.downcast_mut())
     }
 
-    pub fn contains_component<T: Component + StoreComponentsIn>(&self) -> bool {
+    /// Get the storage of the component type `T` as a storage of type `S`. This
+    /// is how to access a component type registered with a different storage
+    /// than its default one. Panics if the storage is borrowed mutably.
+    pub fn get_storage<T: Component, S: OpaqueComponentStorage>(&self) -> Option<StorageRef<'_, S>> {
+        match self.try_borrow_storage::<T, S>() {
+            Ok(storage) => Some(storage),
+            Err(BorrowError::MissingStorage(_)) => None,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Get the storage of the component type `T` without borrowing it.
+    ///
+    /// # Safety
+    ///
+    /// The storage must not be borrowed mutably while the reference is alive.
+    pub(crate) unsafe fn get_opaque_unchecked<T: Component>(&self) -> Option<&dyn OpaqueComponentStorage> {
+        self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .map(|cell| cell.get_unchecked())
+    }
+
+    /// Get the storage of the component type `T` mutably without borrowing it.
+    ///
+    /// # Safety
+    ///
+    /// The storage must not be borrowed at all while the reference is alive.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_opaque_unchecked_mut<T: Component>(&self) -> Option<&mut dyn OpaqueComponentStorage> {
+        self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .map(|cell| cell.get_mut_unchecked())
+    }
+
+    pub fn get_storage_mut<T: Component, S: OpaqueComponentStorage>(&mut self) -> Option<&mut S> {
         let component_type = ComponentTypeIndex::of::<T>();
-        self.data.contains_key(&component_type)
+        self.get_mut(component_type)
+            .and_then(|storage| storage.downcast_mut())
+    }
+
+    /// Borrow the storage of the component type `T` as a storage of type `S`.
+    /// Fails if the storage is borrowed mutably, or if there is no storage of
+    /// type `S` for `T`.
+    pub fn try_borrow_storage<T: Component, S: OpaqueComponentStorage>(&self) -> Result<StorageRef<'_, S>, BorrowError> {
+        let name = any::type_name::<T>();
+        let cell = self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .ok_or(BorrowError::MissingStorage(name))?;
+        let storage = cell.try_borrow().ok_or(BorrowError::AlreadyBorrowed(name))?;
+
+        StorageRef::filter_map(storage, |storage| storage.downcast_ref())
+            .ok_or(BorrowError::MissingStorage(name))
+    }
+
+    /// Borrow the storage of the component type `T`, whatever the type of the
+    /// storage. Fails if the storage is borrowed mutably.
+    pub(crate) fn try_borrow_opaque<T: Component>(&self) -> Result<StorageRef<'_, dyn OpaqueComponentStorage>, BorrowError> {
+        let name = any::type_name::<T>();
+        let cell = self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .ok_or(BorrowError::MissingStorage(name))?;
+
+        cell.try_borrow().ok_or(BorrowError::AlreadyBorrowed(name))
+    }
+
+    /// Like [`ComponentMap::try_borrow_opaque`], borrowing the storage mutably.
+    pub(crate) fn try_borrow_opaque_mut<T: Component>(&self) -> Result<StorageRefMut<'_, dyn OpaqueComponentStorage>, BorrowError> {
+        let name = any::type_name::<T>();
+        let cell = self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .ok_or(BorrowError::MissingStorage(name))?;
+
+        cell.try_borrow_mut().ok_or(BorrowError::AlreadyBorrowed(name))
     }
 
-    pub(crate) fn contains_component_id(&self, index: ComponentTypeIndex) -> bool {
-        self.data.contains_key(&index)
+    /// Borrow the storage of the component type `T` mutably as a storage of
+    /// type `S`. Fails if the storage is borrowed at all, or if there is no
+    /// storage of type `S` for `T`.
+    pub fn try_borrow_storage_mut<T: Component, S: OpaqueComponentStorage>(&self) -> Result<StorageRefMut<'_, S>, BorrowError> {
+        let name = any::type_name::<T>();
+        let cell = self.data
+            .get(&ComponentTypeIndex::of::<T>())
+            .ok_or(BorrowError::MissingStorage(name))?;
+        let storage = cell.try_borrow_mut().ok_or(BorrowError::AlreadyBorrowed(name))?;
+
+        StorageRefMut::filter_map(storage, |storage| storage.downcast_mut())
+            .ok_or(BorrowError::MissingStorage(name))
     }
 
-    pub fn get_multi_view_mut(&mut self) -> MultiViewMut {
+    pub fn contains_component<T: Component + StoreComponentsIn>(&self) -> bool {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data.contains_key(&component_type)
+    }
+
+    pub fn get_multi_view_mut(&mut self) -> MultiViewMut<'_> {
         MultiViewMut::new(self)
     }
 }
 
-pub struct Entry<'a> {
-    location: EntityLocation,
-    world: &'a mut World,
+/// Where the sparse set components live in a world.
+pub struct SparseComponentMap {
+    data: HashMap<ComponentTypeIndex, Box<dyn OpaqueSparseStorage>>,
 }
 
-impl<'a> Entry<'a> {
-    fn new(location: EntityLocation, world: &'a mut World) -> Self {
+impl SparseComponentMap {
+    fn new() -> Self {
         Self {
-            location: location,
-            world: world,
+            data: HashMap::new(),
         }
     }
 
-    pub fn entity_type(&self) -> &EntityType {
-        &self.world.entity_types()[self.location.entity_type().id()]
+    fn get_or_insert<T: Component>(&mut self) -> &mut SparseSetStorage<T> {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data
+            .entry(component_type)
+            .or_insert_with(|| Box::new(SparseSetStorage::<T>::new()))
+            .downcast_mut()
+            .unwrap()
+    }
+
+    pub fn get<T: Component>(&self) -> Option<&SparseSetStorage<T>> {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data
+            .get(&component_type)
+            .and_then(|storage| storage.downcast_ref())
+    }
+
+    pub fn get_mut<T: Component>(&mut self) -> Option<&mut SparseSetStorage<T>> {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data
+            .get_mut(&component_type)
+            .and_then(|storage| storage.downcast_mut())
     }
 
-    pub fn location(&self) -> EntityLocation {
-        self.location
+    pub fn contains_component<T: Component>(&self) -> bool {
+        let component_type = ComponentTypeIndex::of::<T>();
+        self.data.contains_key(&component_type)
     }
 
-    pub fn get_component<T: Component + StoreComponentsIn>(&self) -> Result<&T, ()> {
-        let entity_type = self.location.entity_type();
-        let component = self.location.component();
-        self.world
-            .components()
-            .get_view::<T>()
-            .and_then(move |storage| storage.get(entity_type))
-            .and_then(move |view| view.into_slice().get(component.id()))
-            .ok_or_else(|| {})
+    fn shrink_to_fit(&mut self) {
+        for storage in self.data.values_mut() {
+            storage.shrink_to_fit();
+        }
     }
 
-    pub fn get_component_mut<T: Component + StoreComponentsIn>(&mut self) -> Result<&mut T, ()> {
-        let entity_type = self.location.entity_type();
-        let component = self.location.component();
-        self.world
-            .components_mut()
-            .get_view_mut::<T>()
-            .and_then(move |storage| storage.get_mut(entity_type))
-            .and_then(move |view| view.into_slice().get_mut(component.id()))
-            .ok_or_else(|| {})
+    fn memory_stats(&self) -> Vec<ComponentMemoryStats> {
+        self.data
+            .iter()
+            .map(|(type_id, storage)| {
+                let metadata = storage.metadata();
+                let mut stats = ComponentMemoryStats::new(*type_id, &metadata);
+                stats.add_usage(MemoryUsage::new(
+                    metadata.size(),
+                    storage.len(),
+                    storage.capacity()
+                ));
+
+                stats
+            })
+            .collect()
     }
 
-    pub fn has_component<T: Component + StoreComponentsIn>(&self) -> bool {
-        let entity_type_index = self.location.entity_type();
-        let entity_type = &self.world.entity_types[entity_type_index];
-        
-        entity_type.contains_component::<T>()
+    fn clear(&mut self) {
+        for storage in self.data.values_mut() {
+            storage.clear();
+        }
+    }
+
+    /// Remove every sparse component of an entity.
+    fn remove_entity(&mut self, entity: Entity) {
+        for storage in self.data.values_mut() {
+            storage.remove_entity(entity);
+        }
+    }
+
+    /// Move every sparse component of `other` into this map, renaming the
+    /// entities by `entity_map`.
+    fn merge_from(&mut self, other: &mut SparseComponentMap, entity_map: &HashMap<Entity, Entity>) {
+        for (type_id, mut storage) in other.data.drain() {
+            match self.data.get_mut(&type_id) {
+                Some(dst_storage) => {
+                    storage.transfer_entities(entity_map, dst_storage.as_mut());
+                }
+                None => {
+                    storage.remap_entities(entity_map);
+                    self.data.insert(type_id, storage);
+                }
+            }
+        }
+    }
+}
+
+/// The reason a [`MultiViewMut`] refused to hand out a storage.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    /// The storage is already claimed, mutably or, for a mutable claim, at all.
+    AlreadyClaimed(&'static str),
+    /// The world has no storage for the component type.
+    MissingStorage(&'static str),
+    /// The component type lives in a storage of another type than the one
+    /// claimed, e.g. a storage registered with [`World::register_storage`].
+    StorageMismatch(&'static str),
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClaimError::AlreadyClaimed(name) => {
+                write!(formatter, "The storage of `{}` is already claimed", name)
+            }
+            ClaimError::MissingStorage(name) => {
+                write!(formatter, "There is no storage for `{}`", name)
+            }
+            ClaimError::StorageMismatch(name) => {
+                write!(formatter, "The storage of `{}` is not of the claimed type", name)
+            }
+        }
     }
 }
 
+impl error::Error for ClaimError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Claim {
+    Read,
+    Write,
+}
 
+/// Hands out references to several component storages of a world at once.
+///
+/// Every claim is recorded, so a storage can be claimed any number of times for
+/// reading, or once for writing, but never both. The references live as long as
+/// the borrow of the world the view was created from.
 pub struct MultiViewMut<'a> {
     components: &'a mut ComponentMap,
-    claimed: HashSet<ComponentTypeIndex>,    
+    claimed: HashMap<ComponentTypeIndex, Claim>,
 }
 
 impl<'a> MultiViewMut<'a> {
     fn new(components: &'a mut ComponentMap) -> Self {
         Self {
-            components: components,
-            claimed: HashSet::default(),
+            components,
+            claimed: HashMap::default(),
         }
     }
 
-    pub unsafe fn claim<T: Component + StoreComponentsIn>(&mut self) -> Option<&'a mut T::Storage> {
+    /// Record a claim of a component type, failing if it conflicts with an
+    /// earlier claim, or if `matches` rejects the storage of the component type.
+    fn record_claim<T: Component>(
+        &mut self,
+        claim: Claim,
+        matches: fn(&dyn OpaqueComponentStorage) -> bool,
+    ) -> Result<ComponentTypeIndex, ClaimError>
+    {
         let type_id = ComponentTypeIndex::of::<T>();
-        self.claimed.insert(type_id);
+        let name = any::type_name::<T>();
+        let cell = self.components.data.get(&type_id).ok_or(ClaimError::MissingStorage(name))?;
+        match (self.claimed.get(&type_id), claim) {
+            (None, _) | (Some(Claim::Read), Claim::Read) => {}
+            _ => return Err(ClaimError::AlreadyClaimed(name)),
+        }
 
-        self.components
-            .get_view_mut::<T>()
-            .map(|storage| {
-                mem::transmute::<&mut T::Storage, &'a mut T::Storage>(storage)
-            })
+        // SAFETY: The storage has not been claimed mutably, or the claim would
+        // have conflicted.
+        if !matches(unsafe { cell.get_unchecked() }) {
+            return Err(ClaimError::StorageMismatch(name));
+        }
+        self.claimed.insert(type_id, claim);
+
+        Ok(type_id)
+    }
+
+    /// Determine whether the component type `T` has been claimed.
+    pub fn is_claimed<T: Component>(&self) -> bool {
+        self.claimed.contains_key(&ComponentTypeIndex::of::<T>())
+    }
+
+    /// Claim mutable access to the storage for the component type `T`. Fails if
+    /// the storage has been claimed before.
+    pub fn claim<T: Component + StoreComponentsIn>(&mut self) -> Result<&'a mut T::Storage, ClaimError> {
+        let type_id = self.record_claim::<T>(Claim::Write, |storage| storage.is::<T::Storage>())?;
+        let storage = self.components.get_mut(type_id).unwrap();
+        let storage = storage.downcast_mut::<T::Storage>().unwrap();
+
+        // SAFETY: The view borrows the component map for `'a`, and the claim
+        // ensures this is the only reference to the storage handed out by the view.
+        Ok(unsafe { &mut *(storage as *mut T::Storage) })
+    }
+
+    /// Claim shared access to the storage for the component type `T`. Fails if
+    /// the storage has been claimed mutably.
+    pub fn claim_ref<T: Component + StoreComponentsIn>(&mut self) -> Result<&'a T::Storage, ClaimError> {
+        let type_id = self.record_claim::<T>(Claim::Read, |storage| storage.is::<T::Storage>())?;
+        let storage = unsafe { self.components.data[&type_id].get_unchecked() };
+        let storage = storage.downcast_ref::<T::Storage>().unwrap();
+
+        // SAFETY: The view borrows the component map exclusively for `'a`, and the
+        // claim ensures nobody gets mutable access to this storage through the view.
+        Ok(unsafe { &*(storage as *const T::Storage) })
+    }
+
+    /// Claim mutable access to the storage for the component type `T`, whatever
+    /// kind of storage the component type lives in. Fails if the storage has
+    /// been claimed before.
+    pub fn claim_opaque<T: Component>(&mut self) -> Result<&'a mut dyn OpaqueComponentStorage, ClaimError> {
+        let type_id = self.record_claim::<T>(Claim::Write, |_| true)?;
+        let storage = self.components.get_mut(type_id).unwrap();
+
+        // SAFETY: The view borrows the component map for `'a`, and the claim
+        // ensures this is the only reference to the storage handed out by the view.
+        Ok(unsafe { &mut *(storage as *mut dyn OpaqueComponentStorage) })
     }
 }
 
@@ -178,7 +519,6 @@ pub struct EntityTypeWriter<'a> {
     entity_type_index: EntityTypeIndex,
     entity_type: &'a mut EntityType,
     components: MultiViewMut<'a>,
-    claimed: u128,
     initial_count: usize,
 }
 
@@ -187,15 +527,14 @@ impl<'a> EntityTypeWriter<'a> {
         entity_type_index: EntityTypeIndex,
         entity_type: &'a mut EntityType,
         components: MultiViewMut<'a>,
-    ) -> Self 
+    ) -> Self
     {
         let initial_count = entity_type.entities().len();
         Self {
-            entity_type_index: entity_type_index,
+            entity_type_index,
             entity_type,
-            components: components,
-            claimed: 0,
-            initial_count: initial_count,
+            components,
+            initial_count,
         }
     }
 
@@ -205,26 +544,27 @@ impl<'a> EntityTypeWriter<'a> {
     }
 
     pub fn claim_components<T: Component + StoreComponentsIn>(&mut self) -> ComponentWriter<'a, T> {
-        let component_type_id = ComponentTypeIndex::of::<T>();
-        let components = unsafe {
-            self.components.claim::<T>().unwrap()
+        let components = match self.components.claim_opaque::<T>() {
+            Ok(components) => components,
+            Err(error) => panic!("{}", error),
         };
-        
+
         ComponentWriter {
-            components: components,
+            components,
             entity_type: self.entity_type_index,
+            _marker: PhantomData,
         }
     }
 
     pub fn entity_type(&self) -> &EntityType {
-        &self.entity_type
+        self.entity_type
     }
 
     pub fn inserted(&self) -> (ComponentIndex, &[Entity]) {
         let start = self.initial_count;
         let index = ComponentIndex::new(start);
         let slice = &self.entity_type.entities()[start..];
-        
+
         (index, slice)
     }
 }
@@ -264,10 +604,7 @@ impl<T> EntityTypeSource for Option<T> where T: EntityTypeSource {
     type Filter = Option<T::Filter>;
 
     fn filter(&self) -> Self::Filter {
-        match self {
-            Some(provider) => Some(provider.filter()),
-            None => None,
-        }
+        self.as_ref().map(|provider| provider.filter())
     }
 
     fn layout(&mut self) -> EntityLayout {
@@ -284,29 +621,23 @@ impl<T> ComponentSource for Option<T> where T: ComponentSource {
         writer: &mut EntityTypeWriter<'a>,
         entities: impl Iterator<Item = Entity>,
     ) {
-        match self {
-            Some(provider) => {
-                <T as ComponentSource>::push_components(provider, writer, entities)
-            }
-            None => {}
+        if let Some(provider) = self {
+            <T as ComponentSource>::push_components(provider, writer, entities)
         }
     }
 }
 
 impl<T> IntoComponentSource for Option<T> where T: IntoComponentSource {
     type Source = Option<T::Source>;
-    
+
     fn into(self) -> Self::Source {
-        match self {
-            Some(provider) => Some(provider.into()),
-            None => None,
-        }
+        self.map(|provider| provider.into())
     }
 }
 
 
 pub struct SingleEntity<T> {
-    data: T,
+    data: Option<T>,
 }
 
 use std::marker::PhantomData;
@@ -325,11 +656,11 @@ where
 {
     fn matches_layout(&self, components: &[ComponentTypeIndex]) -> bool {
         let type_array = [
-            ComponentTypeIndex::of::<T1>(), 
+            ComponentTypeIndex::of::<T1>(),
             ComponentTypeIndex::of::<T2>()
         ];
 
-        type_array.len() == components.len() 
+        type_array.len() == components.len()
             && type_array.iter().all(|type_id| components.contains(type_id))
     }
 }
@@ -356,7 +687,7 @@ where
     }
 }
 
-impl<T1, T2> ComponentSource for SingleEntity<(T1, T2)> 
+impl<T1, T2> ComponentSource for SingleEntity<(T1, T2)>
 where
     T1: Component + StoreComponentsIn,
     T2: Component + StoreComponentsIn,
@@ -371,9 +702,12 @@ where
         writer.push(entity.unwrap());
         let mut writer_t1 = writer.claim_components::<T1>();
         let mut writer_t2 = writer.claim_components::<T2>();
+        // The storages take ownership of the components, so they must not be
+        // dropped here.
+        let data = mem::ManuallyDrop::new(self.data.take().unwrap());
         unsafe {
-            writer_t1.extend_memcopy(&self.data.0 as *const T1, 1);
-            writer_t2.extend_memcopy(&self.data.1 as *const T2, 1);
+            writer_t1.extend_memcopy(&data.0 as *const T1, 1);
+            writer_t2.extend_memcopy(&data.1 as *const T2, 1);
         }
     }
 }
@@ -386,58 +720,133 @@ pub trait IntoComponentSource {
 
 
 impl<T1, T2> IntoComponentSource for (T1, T2)
-where 
-    T1: Component + StoreComponentsIn, 
+where
+    T1: Component + StoreComponentsIn,
     T2: Component + StoreComponentsIn,
 {
     type Source = SingleEntity<(T1, T2)>;
 
     fn into(self) -> Self::Source {
         SingleEntity {
-            data: self,
+            data: Some(self),
         }
     }
 }
 
 pub struct ComponentWriter<'a, T: Component + StoreComponentsIn> {
-    components: &'a mut T::Storage,
+    components: &'a mut dyn OpaqueComponentStorage,
     entity_type: EntityTypeIndex,
+    _marker: PhantomData<T>,
 }
 
 impl<'a, T> ComponentWriter<'a, T>
 where
     T: Component + StoreComponentsIn
 {
+    /// Copy `len` components starting at `ptr` to the end of the entity type's components.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to `len` valid, initialized values of `T`. The values
+    /// are moved into the storage, so the caller must not drop them afterwards.
     pub unsafe fn extend_memcopy(&mut self, ptr: *const T, len: usize) {
-        <T::Storage as ComponentStorage<'a,_>>::extend_memcopy(
-            &mut self.components, 
-            self.entity_type,
-            ptr, 
-            len
-        );
+        self.components.extend_memcopy_raw(self.entity_type, ptr as *const u8, len);
     }
 }
 
+/// Determines when a world releases the excess capacity of an entity type
+/// after removing entities from it.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CompactionPolicy {
+    /// Only release excess capacity when calling [`World::compact`].
+    #[default]
+    Manual,
+    /// Release the excess capacity of an entity type as soon as the fraction of
+    /// its capacity occupied by entities falls below the threshold.
+    Occupancy(f32),
+}
+
+/// Determines what [`World::clear_with`] keeps of a world besides its entities.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ClearMode {
+    /// Keep the entity types and the capacity of their component arrays, so the
+    /// world can be refilled with the same kinds of entities without reallocating.
+    #[default]
+    Retain,
+    /// Drop the entity types along with their component arrays, and release the
+    /// excess capacity of the rest of the world.
+    Release,
+}
+
 /// Where all the data is grouped together.
 pub struct World {
     entities: EntityLocationMap,
     entity_types: Vec<EntityType>,
     entity_allocator: EntityAllocator,
     components: ComponentMap,
+    sparse_components: SparseComponentMap,
+    relations: RelationMap,
     allocation_buffer: Vec<Entity>,
+    compaction_policy: CompactionPolicy,
+    allocator: Arc<dyn ComponentAllocator>,
+    column_alignment: usize,
+    component_alignments: HashMap<ComponentTypeIndex, usize>,
+    registry: ComponentRegistry,
+    layout_epoch: u64,
+}
+
+/// The source of layout epochs, shared by every world so no two worlds ever
+/// have the same epoch.
+static NEXT_LAYOUT_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+fn next_layout_epoch() -> u64 {
+    NEXT_LAYOUT_EPOCH.fetch_add(1, Ordering::Relaxed)
 }
 
 impl World {
     pub fn new() -> World {
+        World::with_allocator(GlobalAllocator::shared())
+    }
+
+    /// Construct a world whose component storages allocate their component
+    /// arrays from `allocator`.
+    pub fn with_allocator(allocator: Arc<dyn ComponentAllocator>) -> World {
+        World::with_allocators(EntityAllocator::new(), allocator)
+    }
+
+    pub(crate) fn with_allocators(
+        entity_allocator: EntityAllocator,
+        allocator: Arc<dyn ComponentAllocator>,
+    ) -> World
+    {
         World {
             entities: EntityLocationMap::new(),
             entity_types: Vec::new(),
-            entity_allocator: EntityAllocator::new(),
+            entity_allocator,
             components: ComponentMap::new(),
+            sparse_components: SparseComponentMap::new(),
+            relations: RelationMap::new(),
             allocation_buffer: Vec::new(),
+            compaction_policy: CompactionPolicy::default(),
+            allocator,
+            column_alignment: 1,
+            component_alignments: HashMap::new(),
+            registry: World::default_registry(),
+            layout_epoch: next_layout_epoch(),
         }
     }
 
+    /// The registry of a new world, with the built in components registered.
+    fn default_registry() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::new();
+        registry.register_map_entities::<Parent>();
+        registry.register_map_entities::<Children>();
+        registry.register_clone::<Parent>();
+        registry.register_clone::<Children>();
+
+        registry
+    }
+
     pub fn len(&self) -> usize {
         self.entities.len()
     }
@@ -450,6 +859,11 @@ impl World {
         self.entities.contains(entity)
     }
 
+    /// The location of an entity: its entity type, and its index in it.
+    pub fn location(&self, entity: Entity) -> Option<EntityLocation> {
+        self.entities.get(entity)
+    }
+
     pub fn contains_component<T: Component + StoreComponentsIn>(&self) -> bool {
         self.components.contains_component::<T>()
     }
@@ -464,7 +878,36 @@ impl World {
         }
     }
 
-    fn get_entity_type_for_components<T>(&mut self, components: &mut T) -> EntityTypeIndex 
+    /// Swap the components of type `T` of two entities. Returns `false` if either
+    /// entity does not exist or has no component of type `T`.
+    pub fn swap_components<T: Component + StoreComponentsIn>(&mut self, a: Entity, b: Entity) -> bool {
+        let (location_a, location_b) = match (self.entities.get(a), self.entities.get(b)) {
+            (Some(location_a), Some(location_b)) => (location_a, location_b),
+            _ => return false,
+        };
+        let storage = match self.components.get_mut(ComponentTypeIndex::of::<T>()) {
+            Some(storage) => storage,
+            None => return false,
+        };
+        if location_a == location_b {
+            return storage.get_component_ptr(location_a.entity_type(), location_a.component()).is_some();
+        }
+        unsafe {
+            let ptr_a = storage.get_component_ptr_mut(location_a.entity_type(), location_a.component());
+            let ptr_b = storage.get_component_ptr_mut(location_b.entity_type(), location_b.component());
+            match (ptr_a, ptr_b) {
+                (Some(ptr_a), Some(ptr_b)) => {
+                    // The storage for a component type only holds values of that type.
+                    ptr::swap(ptr_a as *mut T, ptr_b as *mut T);
+
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    fn get_entity_type_for_components<T>(&mut self, components: &mut T) -> EntityTypeIndex
     where
         T: EntityTypeSource,
     {
@@ -479,38 +922,41 @@ impl World {
         };
 
         let index = search_entities(&components.filter());
-        let entity_type_index = if let Some(value) = index {
+        if let Some(value) = index {
             value
         } else {
             self.insert_entity_type(components.layout())
-        };
+        }
+    }
 
-        entity_type_index
+    /// Find the entity type with exactly the given components.
+    fn find_entity_type(&self, components: &[ComponentTypeIndex]) -> Option<EntityTypeIndex> {
+        self.entity_types
+            .iter()
+            .find(|entity_type| {
+                let component_types = entity_type.layout().component_types();
+                component_types.len() == components.len()
+                    && components.iter().all(|type_id| component_types.contains(type_id))
+            })
+            .map(|entity_type| entity_type.index())
     }
 
     fn insert_entity_type(&mut self, layout: EntityLayout) -> EntityTypeIndex {
         let entity_type_index = EntityTypeIndex::new(self.entity_types.len());
         self.entity_types.push(EntityType::new(entity_type_index, layout));
         let entity_type = &self.entity_types[self.entity_types.len() - 1];
-        let missing_components: Vec<ComponentTypeIndex> = entity_type
-            .layout()
-            .component_types()
-            .iter()
-            .filter(|type_id| {
-                !self.components.contains_component_id(**type_id)
-            })
-            .map(|p| *p)
-            .collect();
-
-        for missing_component in missing_components.iter() {
-            self.components.get_or_insert_with(*missing_component, || { 
-                let mut storage = entity_type
-                    .layout()
-                    .get_constructor_unchecked(*missing_component)();
-                storage.insert_entity_type(entity_type_index);
+        for type_id in entity_type.layout().component_types() {
+            let constructor = entity_type.layout().get_constructor_unchecked(*type_id);
+            let allocator = &self.allocator;
+            let alignment = self.alignment_of(*type_id);
+            let storage = self.components.get_or_insert_with(*type_id, || {
+                let mut storage = constructor();
+                storage.set_allocator(allocator.clone());
+                storage.set_alignment(alignment);
 
                 storage
             });
+            storage.insert_entity_type(entity_type_index);
         }
 
         entity_type_index
@@ -574,12 +1020,349 @@ impl World {
     }
 
     pub fn remove(&mut self, entity: Entity) -> bool {
-        if let Some(location) = self.entities.remove(entity) {
-            self.remove_at_location(location);
+        if !self.entities.contains(entity) {
+            return false;
+        }
 
-            true
-        } else {
-            false
+        self.remove_cascading(vec![entity]);
+
+        true
+    }
+
+    /// Remove entities along with the sources of relations that are removed
+    /// with their targets. The sources are queued rather than removed
+    /// recursively, so long chains of relations cannot overflow the stack.
+    fn remove_cascading(&mut self, mut pending: Vec<Entity>) {
+        while let Some(entity) = pending.pop() {
+            if self.entities.contains(entity) {
+                self.detach_from_hierarchy(entity);
+            }
+
+            if let Some(location) = self.entities.remove(entity) {
+                self.remove_at_location(location);
+                self.sparse_components.remove_entity(entity);
+                pending.extend(self.relations.remove_entity(entity));
+            }
+        }
+    }
+
+    /// Keep only the entities for which `f` returns `true`, removing the others
+    /// as [`World::remove`] would. See [`World::drain_filter`].
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Entry<'_>) -> bool,
+    {
+        self.drain_filter(|entry| !f(entry));
+    }
+
+    /// Remove every entity for which `f` returns `true`, as [`World::remove`]
+    /// would, and return the removed entities.
+    ///
+    /// Every entity is passed to `f` before any is removed, walking the entity
+    /// types in order. The removals are then batched per entity type: the
+    /// entity type and each of its columns are compacted in a single pass,
+    /// keeping the order of the remaining entities.
+    pub fn drain_filter<F>(&mut self, mut f: F) -> Vec<Entity>
+    where
+        F: FnMut(&mut Entry<'_>) -> bool,
+    {
+        let entity_types: Vec<Vec<Entity>> = self.entity_types
+            .iter()
+            .map(|entity_type| entity_type.entities().to_vec())
+            .collect();
+        let mut drained = Vec::new();
+        // Entities stay where the walk expects them until `f` moves one, after
+        // which they have to be looked up.
+        let mut moved = false;
+        for (id, entities) in entity_types.iter().enumerate() {
+            for (index, entity) in entities.iter().enumerate() {
+                let location = if moved {
+                    match self.entities.get(*entity) {
+                        Some(location) => location,
+                        None => continue,
+                    }
+                } else {
+                    EntityLocation::new(EntityTypeIndex::new(id), ComponentIndex::new(index))
+                };
+                let mut entry = Entry::new(location, self);
+                let drain = f(&mut entry);
+                moved |= entry.location() != location;
+                if drain {
+                    drained.push(*entity);
+                }
+            }
+        }
+        self.remove_batch(&drained);
+
+        drained
+    }
+
+    /// Move every entity of `other` into this world, leaving `other` empty.
+    ///
+    /// The entities of each entity type of `other` are appended to the entity type
+    /// with the same components in this world, which is created if necessary,
+    /// moving the components one whole array at a time. Entities keep their ids
+    /// unless the id is already in use in this world, or the worlds allocate ids
+    /// from different id spaces, in which case they get a new one. Returns the map
+    /// from the entities of `other` to their entities in this world.
+    pub fn merge_from(&mut self, other: &mut World) -> HashMap<Entity, Entity> {
+        let same_id_space = self.entity_allocator.shares_ids_with(&other.entity_allocator);
+        let (conflicting, free): (Vec<Entity>, Vec<Entity>) = other.entity_types
+            .iter()
+            .flat_map(|entity_type| entity_type.entities().iter().copied())
+            .partition(|entity| !same_id_space || self.entities.contains(*entity));
+        let mut entity_map = HashMap::with_capacity(conflicting.len() + free.len());
+        self.entity_allocator.reserve(free.iter().copied());
+        for entity in free {
+            entity_map.insert(entity, entity);
+        }
+        for entity in conflicting {
+            entity_map.insert(entity, self.entity_allocator.allocate());
+        }
+
+        for src_entity_type in other.entity_types.iter_mut() {
+            if src_entity_type.is_empty() {
+                continue;
+            }
+
+            let src_index = src_entity_type.index();
+            let layout = src_entity_type.layout().clone();
+            let dst_index = match self.find_entity_type(layout.component_types()) {
+                Some(dst_index) => dst_index,
+                None => self.insert_entity_type(EntityLayout::clone(&layout)),
+            };
+            for type_id in layout.component_types() {
+                let src_storage = other.components.get_mut(*type_id).unwrap();
+                let dst_storage = self.components.get_mut(*type_id).unwrap();
+                src_storage.transfer_entity_type(src_index, dst_index, dst_storage);
+            }
+
+            let dst_entity_type = &mut self.entity_types[dst_index];
+            let base = ComponentIndex::new(dst_entity_type.entities().len());
+            let new_entities: Vec<Entity> = src_entity_type
+                .take_entities()
+                .into_iter()
+                .map(|entity| entity_map[&entity])
+                .collect();
+            for entity in new_entities.iter() {
+                dst_entity_type.push(*entity);
+            }
+            self.entities.insert(&new_entities, dst_index, base);
+        }
+
+        for entity in entity_map.keys() {
+            other.entities.remove(*entity);
+        }
+        self.sparse_components.merge_from(&mut other.sparse_components, &entity_map);
+        self.relations.merge_from(&mut other.relations, &entity_map);
+        self.registry.merge(&other.registry);
+        self.map_entities(&entity_map);
+
+        entity_map
+    }
+
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut ComponentRegistry {
+        &mut self.registry
+    }
+
+    /// Register the [`MapEntities`] implementation of `T`, so that the world
+    /// remaps the entities held by `T` components whenever entities change ids.
+    pub fn register_map_entities<T: Component + MapEntities>(&mut self) {
+        self.registry.register_map_entities::<T>();
+    }
+
+    /// Register the `Clone` implementation of `T`, so that entities and worlds
+    /// with `T` components can be cloned.
+    pub fn register_clone<T: Component + Clone>(&mut self) {
+        self.registry.register_clone::<T>();
+    }
+
+    /// Register the `Deserialize` implementation of `T` under `name`, so that
+    /// prefabs loaded with the world's registry can hold `T` components.
+    #[cfg(feature = "serialize")]
+    pub fn register_deserialize<T>(&mut self, name: &str)
+    where
+        T: Component + Clone + StoreComponentsIn + serde::de::DeserializeOwned,
+    {
+        self.registry.register_deserialize::<T>(name);
+    }
+
+    /// The clone functions of a component type, if it registered any.
+    fn clone_fns(&self, component_type: ComponentTypeIndex) -> Option<CloneFns> {
+        self.registry
+            .get(component_type)
+            .and_then(|registration| registration.clone_fns())
+    }
+
+    /// Spawn a new entity with clones of the components of `entity`, including
+    /// its sparse components. The clone gets the same parent as the entity, but
+    /// none of its children. Returns `None` if the entity does not exist or one
+    /// of its components has no registered `Clone` implementation, in which case
+    /// nothing is cloned.
+    pub fn clone_entity(&mut self, entity: Entity) -> Option<Entity> {
+        let location = self.entities.get(entity)?;
+        let entity_type_index = location.entity_type();
+        let layout = self.entity_types[entity_type_index].layout().clone();
+        let component_fns = layout
+            .component_types()
+            .iter()
+            .map(|type_id| self.clone_fns(*type_id).map(|fns| (*type_id, fns)))
+            .collect::<Option<Vec<_>>>()?;
+        let hierarchy_types = [ComponentTypeIndex::of::<Parent>(), ComponentTypeIndex::of::<Children>()];
+        let sparse_fns = self.sparse_components.data
+            .iter()
+            .filter(|(type_id, storage)| storage.contains(entity) && !hierarchy_types.contains(type_id))
+            .map(|(type_id, _)| self.clone_fns(*type_id).map(|fns| (*type_id, fns)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let new_entity = self.entity_allocator.allocate();
+        for (type_id, fns) in component_fns {
+            let storage = self.components.get_mut(type_id).unwrap();
+            (fns.clone_component)(storage, entity_type_index, location.component(), entity_type_index);
+        }
+        let entity_type = &mut self.entity_types[entity_type_index];
+        let base = ComponentIndex::new(entity_type.entities().len());
+        entity_type.push(new_entity);
+        self.entities.insert(&[new_entity], entity_type_index, base);
+        for (type_id, fns) in sparse_fns {
+            let storage = self.sparse_components.data.get_mut(&type_id).unwrap();
+            (fns.clone_sparse_component)(storage.as_mut(), entity, new_entity);
+        }
+        self.relations.clone_source(entity, new_entity);
+        if let Some(parent) = self.parent(entity) {
+            self.set_parent(new_entity, parent);
+        }
+
+        Some(new_entity)
+    }
+
+    /// Construct a deep copy of the world, cloning every component. The copy has
+    /// the same entities, entity types and configuration as the world, and its
+    /// entities keep their ids. Returns `None` if a component type in the world
+    /// has no registered `Clone` implementation.
+    ///
+    /// A world of a [`Universe`](crate::Universe) hands its copy a fresh block of
+    /// ids, so the entities either world creates afterwards never share an id.
+    /// The copy of a standalone world continues with the same ids as the world.
+    pub fn clone_world(&self) -> Option<World> {
+        let mut components = ComponentMap::new();
+        for type_id in self.components.data.keys() {
+            let fns = self.clone_fns(*type_id)?;
+            let storage = self.components.get(*type_id).unwrap();
+            let mut new_storage = storage.new_empty();
+            for entity_type in self.entity_types.iter() {
+                if entity_type.layout().component_types().contains(type_id) {
+                    new_storage.insert_entity_type(entity_type.index());
+                    (fns.clone_entity_type)(&*storage, entity_type.index(), new_storage.as_mut());
+                }
+            }
+            components.insert(new_storage);
+        }
+
+        let mut sparse_components = SparseComponentMap::new();
+        for (type_id, storage) in self.sparse_components.data.iter() {
+            let fns = self.clone_fns(*type_id)?;
+            sparse_components.data.insert(*type_id, (fns.clone_sparse)(storage.as_ref()));
+        }
+
+        Some(World {
+            entities: self.entities.clone(),
+            entity_types: self.entity_types.clone(),
+            entity_allocator: self.entity_allocator.fork(),
+            components,
+            sparse_components,
+            relations: self.relations.clone(),
+            allocation_buffer: Vec::new(),
+            compaction_policy: self.compaction_policy,
+            allocator: self.allocator.clone(),
+            column_alignment: self.column_alignment,
+            component_alignments: self.component_alignments.clone(),
+            registry: self.registry.clone(),
+            layout_epoch: next_layout_epoch(),
+        })
+    }
+
+    /// Spawn an entity from a prefab, along with the entities of its child
+    /// prefabs, which become its children in the hierarchy. Returns the entity
+    /// of the prefab itself.
+    pub fn spawn_prefab(&mut self, prefab: &Prefab) -> Entity {
+        self.spawn_prefabs(prefab, 1)[0]
+    }
+
+    /// Spawn `count` entities from a prefab, along with `count` entities for each
+    /// of its child prefabs. The entity type of the prefab is resolved once, and
+    /// the clones of each component are written in one batch. Returns the
+    /// entities of the prefab itself.
+    pub fn spawn_prefabs(&mut self, prefab: &Prefab, count: usize) -> Vec<Entity> {
+        let layout = prefab.layout();
+        let entity_type_index = match self.find_entity_type(layout.component_types()) {
+            Some(entity_type_index) => entity_type_index,
+            None => self.insert_entity_type(layout.clone()),
+        };
+        for component in prefab.components() {
+            let storage = self.components.get_mut(component.component_type()).unwrap();
+            component.clone_components(storage, entity_type_index, count);
+        }
+
+        let entities: Vec<Entity> = self.entity_allocator.by_ref().take(count).collect();
+        let entity_type = &mut self.entity_types[entity_type_index];
+        let base = ComponentIndex::new(entity_type.entities().len());
+        for entity in entities.iter() {
+            entity_type.push(*entity);
+        }
+        self.entities.insert(&entities, entity_type_index, base);
+
+        for child in prefab.children() {
+            let children = self.spawn_prefabs(child, count);
+            for (child, parent) in children.into_iter().zip(entities.iter()) {
+                self.set_parent(child, *parent);
+            }
+        }
+
+        entities
+    }
+
+    /// Remap the entity references held by the components of the entities that
+    /// `entity_map` maps to, using the [`MapEntities`] implementations in the
+    /// world's registry. Merging worlds does this automatically. Loaders that
+    /// create entities with new ids should call this once all the entities
+    /// have been created.
+    pub fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        let entities: Vec<Entity> = entity_map
+            .values()
+            .copied()
+            .filter(|entity| self.entities.contains(*entity))
+            .collect();
+        for (type_id, registration) in self.registry.iter() {
+            let fns = match registration.map_entities_fns() {
+                Some(fns) => fns,
+                None => continue,
+            };
+
+            if let Some(storage) = self.components.get_mut(*type_id) {
+                for entity in entities.iter() {
+                    let location = self.entities.get(*entity).unwrap();
+                    let entity_type = &self.entity_types[location.entity_type()];
+                    if !entity_type.layout().component_types().contains(type_id) {
+                        continue;
+                    }
+
+                    unsafe {
+                        let ptr = storage
+                            .get_component_ptr_mut(location.entity_type(), location.component())
+                            .unwrap();
+                        (fns.map_component)(ptr, entity_map);
+                    }
+                }
+            }
+
+            if let Some(storage) = self.sparse_components.data.get_mut(type_id) {
+                (fns.map_sparse)(storage.as_mut(), &entities, entity_map);
+            }
         }
     }
 
@@ -597,12 +1380,319 @@ impl World {
             let swapped = entity_type.entities()[component_index.id()];
             self.entities.set(swapped, location);
         }
+
+        if let CompactionPolicy::Occupancy(threshold) = self.compaction_policy {
+            if entity_type.occupancy() < threshold {
+                self.compact_entity_type(entity_type_index);
+            }
+        }
     }
 
-    pub fn clear(&mut self) {
-        let entities: Vec<Entity> = self.entities.iter().map(|e| *e).collect();
+    /// Remove distinct entities of the world, one entity type at a time.
+    fn remove_batch(&mut self, entities: &[Entity]) {
+        for entity in entities.iter() {
+            self.detach_from_hierarchy(*entity);
+        }
+
+        let mut removed: Vec<Vec<bool>> = vec![Vec::new(); self.entity_types.len()];
+        for entity in entities.iter() {
+            let location = self.entities.remove(*entity).unwrap();
+            let flags = &mut removed[location.entity_type().id()];
+            if flags.is_empty() {
+                flags.resize(self.entity_types[location.entity_type()].entities().len(), false);
+            }
+            flags[location.component().id()] = true;
+        }
+        for (id, flags) in removed.into_iter().enumerate() {
+            if !flags.is_empty() {
+                self.remove_marked(EntityTypeIndex::new(id), flags);
+            }
+        }
+
+        let mut sources = Vec::new();
         for entity in entities.iter() {
-            self.remove(*entity);
+            self.sparse_components.remove_entity(*entity);
+            sources.extend(self.relations.remove_entity(*entity));
+        }
+        self.remove_cascading(sources);
+    }
+
+    /// Remove the entities of an entity type whose flag in `removed` is set,
+    /// compacting the entity type and each of its columns in a single pass.
+    fn remove_marked(&mut self, entity_type_index: EntityTypeIndex, removed: Vec<bool>) {
+        let first = match removed.iter().position(|removed| *removed) {
+            Some(first) => first,
+            None => return,
+        };
+        let entity_type = &mut self.entity_types[entity_type_index];
+        entity_type.remove_marked(&removed);
+        for type_id in entity_type.layout().component_types() {
+            let storage = self.components.get_mut(*type_id).unwrap();
+            storage.remove_marked(entity_type_index, &removed);
+        }
+
+        // Every entity after the first removed one has moved down.
+        for (index, entity) in entity_type.entities().iter().enumerate().skip(first) {
+            self.entities.set(*entity, EntityLocation::new(entity_type_index, ComponentIndex::new(index)));
+        }
+
+        if let CompactionPolicy::Occupancy(threshold) = self.compaction_policy {
+            if entity_type.occupancy() < threshold {
+                self.compact_entity_type(entity_type_index);
+            }
+        }
+    }
+
+    /// Remove the component of type `T` of the entity at `location`, moving the
+    /// rest of its components to the entity type without `T`. Returns the
+    /// component along with the new location of the entity.
+    pub(crate) fn take_component_at<T: Component>(&mut self, location: EntityLocation) -> Option<(T, EntityLocation)> {
+        let type_id = ComponentTypeIndex::of::<T>();
+        let src = location.entity_type();
+        let index = location.component();
+        let layout = Arc::clone(self.entity_types[src].layout());
+        if !layout.component_types().contains(&type_id) {
+            return None;
+        }
+
+        let dst_layout = layout.without_component(type_id);
+        let dst = match self.find_entity_type(dst_layout.component_types()) {
+            Some(dst) => dst,
+            None => self.insert_entity_type(dst_layout),
+        };
+        let mut component = mem::MaybeUninit::<T>::uninit();
+        for component_type in layout.component_types() {
+            let storage = self.components.get_mut(*component_type).unwrap();
+            if *component_type == type_id {
+                unsafe {
+                    storage.swap_remove_raw(src, index, component.as_mut_ptr() as *mut u8);
+                }
+            } else {
+                storage.move_component(src, index, dst);
+            }
+        }
+
+        let src_entity_type = &mut self.entity_types[src];
+        let entity = src_entity_type.swap_remove(index.id());
+        if src_entity_type.contains_component_value(index.id()) {
+            let swapped = src_entity_type.entities()[index.id()];
+            self.entities.set(swapped, location);
+        }
+        let dst_entity_type = &mut self.entity_types[dst];
+        dst_entity_type.push(entity);
+        let new_location = EntityLocation::new(dst, ComponentIndex::new(dst_entity_type.entities().len() - 1));
+        self.entities.set(entity, new_location);
+
+        if let CompactionPolicy::Occupancy(threshold) = self.compaction_policy {
+            if self.entity_types[src].occupancy() < threshold {
+                self.compact_entity_type(src);
+            }
+        }
+
+        // SAFETY: The layout contains `T`, so the component has been written.
+        Some((unsafe { component.assume_init() }, new_location))
+    }
+
+    /// The allocator the world's component storages allocate from.
+    pub fn allocator(&self) -> &Arc<dyn ComponentAllocator> {
+        &self.allocator
+    }
+
+    pub fn compaction_policy(&self) -> CompactionPolicy {
+        self.compaction_policy
+    }
+
+    pub fn set_compaction_policy(&mut self, policy: CompactionPolicy) {
+        self.compaction_policy = policy;
+    }
+
+    /// The minimum alignment in bytes of the component columns of storages
+    /// created by the world.
+    pub fn column_alignment(&self) -> usize {
+        self.column_alignment
+    }
+
+    /// Over-align the component columns of every storage the world creates from
+    /// now on to at least `alignment` bytes, e.g. 32 or 64 for SIMD loads or to
+    /// keep columns on their own cache lines. Storages that already exist keep
+    /// their alignment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn set_column_alignment(&mut self, alignment: usize) {
+        assert!(alignment.is_power_of_two());
+        self.column_alignment = alignment;
+    }
+
+    /// Over-align the component columns of `T` to at least `alignment` bytes,
+    /// overriding the world's column alignment. Returns `false` if the storage
+    /// of `T` already holds entity types, in which case its alignment is unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn set_component_alignment<T: Component>(&mut self, alignment: usize) -> bool {
+        assert!(alignment.is_power_of_two());
+        let type_id = ComponentTypeIndex::of::<T>();
+        if let Some(storage) = self.components.get_mut(type_id) {
+            if !storage.set_alignment(alignment) {
+                return false;
+            }
+        }
+        self.component_alignments.insert(type_id, alignment);
+
+        true
+    }
+
+    /// The alignment of the component columns of a component type.
+    fn alignment_of(&self, type_id: ComponentTypeIndex) -> usize {
+        self.component_alignments
+            .get(&type_id)
+            .copied()
+            .unwrap_or(self.column_alignment)
+    }
+
+    /// Release the excess capacity of the entities and components of an entity type.
+    fn compact_entity_type(&mut self, entity_type_index: EntityTypeIndex) {
+        let entity_type = &mut self.entity_types[entity_type_index];
+        entity_type.shrink_to_fit();
+        for type_id in entity_type.layout().component_types() {
+            let storage = self.components.get_mut(*type_id).unwrap();
+            storage.shrink_to_fit(entity_type_index);
+        }
+    }
+
+    /// Remove every entity type that contains no entities, along with its
+    /// slices in the component storages. The remaining entity types are
+    /// renumbered to keep the entity type indices packed, so entity locations
+    /// obtained before calling this function are invalidated. Returns the number
+    /// of entity types removed.
+    pub fn remove_empty_entity_types(&mut self) -> usize {
+        let mut remap = Vec::with_capacity(self.entity_types.len());
+        let mut retained = 0;
+        for entity_type in self.entity_types.iter() {
+            if entity_type.is_empty() {
+                remap.push(None);
+            } else {
+                remap.push(Some(EntityTypeIndex::new(retained)));
+                retained += 1;
+            }
+        }
+
+        let removed = self.entity_types.len() - retained;
+        if removed == 0 {
+            return 0;
+        }
+
+        for cell in self.components.data.values_mut() {
+            cell.get_mut().remap_entity_types(&remap);
+        }
+        self.entity_types.retain(|entity_type| !entity_type.is_empty());
+        for (id, entity_type) in self.entity_types.iter_mut().enumerate() {
+            entity_type.set_index(EntityTypeIndex::new(id));
+        }
+        self.entities.remap_entity_types(&remap);
+        self.layout_epoch = next_layout_epoch();
+
+        removed
+    }
+
+    /// Compute the memory used by each entity type, component storage and sparse
+    /// set in the world.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut entity_types = Vec::with_capacity(self.entity_types.len());
+        let mut components: HashMap<ComponentTypeIndex, ComponentMemoryStats> = HashMap::new();
+        for entity_type in self.entity_types.iter() {
+            let entity_type_index = entity_type.index();
+            let entities = MemoryUsage::new(
+                mem::size_of::<Entity>(),
+                entity_type.entities().len(),
+                entity_type.capacity()
+            );
+            let mut entity_type_stats = EntityTypeMemoryStats::new(entity_type_index, entities);
+            for type_id in entity_type.layout().component_types() {
+                let storage = self.components.get(*type_id).unwrap();
+                let metadata = storage.metadata();
+                let length = storage.count(entity_type_index).unwrap();
+                let capacity = storage.capacity(entity_type_index).unwrap();
+                let usage = MemoryUsage::new(metadata.size(), length, capacity);
+
+                let mut column_stats = ComponentMemoryStats::new(*type_id, &metadata);
+                column_stats.add_usage(usage);
+                entity_type_stats.push(column_stats);
+                components
+                    .entry(*type_id)
+                    .or_insert_with(|| ComponentMemoryStats::new(*type_id, &metadata))
+                    .add_usage(usage);
+            }
+
+            entity_types.push(entity_type_stats);
+        }
+
+        MemoryStats::new(
+            entity_types,
+            components.into_values().collect(),
+            self.sparse_components.memory_stats()
+        )
+    }
+
+    /// Release the excess capacity of every entity type, component storage and
+    /// sparse set in the world.
+    pub fn compact(&mut self) {
+        for id in 0..self.entity_types.len() {
+            self.compact_entity_type(EntityTypeIndex::new(id));
+        }
+        self.sparse_components.shrink_to_fit();
+        self.entities.shrink_to_fit();
+        self.allocation_buffer.shrink_to_fit();
+    }
+
+    /// Remove every entity from the world, keeping its entity types and their
+    /// capacity. This is [`World::clear_with`] with [`ClearMode::Retain`].
+    pub fn clear(&mut self) {
+        self.clear_with(ClearMode::Retain);
+    }
+
+    /// Remove every entity from the world along with its sparse components and
+    /// relations.
+    ///
+    /// Unlike removing the entities one by one, every component array is emptied
+    /// in one go, without swapping components around or updating locations.
+    ///
+    /// Entity ids are not reset, so entities pushed after a clear keep counting
+    /// up from the ids handed out before it. This is intentional: entities carry
+    /// no generation, so reusing an id would let a stale handle to a removed
+    /// entity silently refer to a new one.
+    pub fn clear_with(&mut self, mode: ClearMode) {
+        for entity_type in self.entity_types.iter_mut() {
+            entity_type.clear();
+        }
+
+        match mode {
+            ClearMode::Retain => {
+                for cell in self.components.data.values_mut() {
+                    cell.get_mut().clear();
+                }
+            }
+            ClearMode::Release => {
+                let remap = vec![None; self.entity_types.len()];
+                for cell in self.components.data.values_mut() {
+                    cell.get_mut().remap_entity_types(&remap);
+                }
+                self.entity_types.clear();
+                self.layout_epoch = next_layout_epoch();
+            }
+        }
+        self.entities.clear();
+        self.sparse_components.clear();
+        self.relations.clear();
+
+        if mode == ClearMode::Release {
+            self.entity_types.shrink_to_fit();
+            self.sparse_components.shrink_to_fit();
+            self.entities.shrink_to_fit();
+            self.allocation_buffer.shrink_to_fit();
         }
     }
 
@@ -614,11 +1704,118 @@ impl World {
         &mut self.components
     }
 
+    /// Borrow the storage of the component type `T` through a shared reference to
+    /// the world, so storages of different component types can be used from
+    /// different threads at once. Returns `None` if the world has no storage for
+    /// `T`, and panics if the storage is borrowed mutably.
+    pub fn borrow<T: Component + StoreComponentsIn>(&self) -> Option<StorageRef<'_, T::Storage>> {
+        self.components.get_view::<T>()
+    }
+
+    /// Borrow the storage of the component type `T` mutably through a shared
+    /// reference to the world. Returns `None` if the world has no storage for
+    /// `T`, and panics if the storage is borrowed.
+    pub fn borrow_mut<T: Component + StoreComponentsIn>(&self) -> Option<StorageRefMut<'_, T::Storage>> {
+        match self.try_borrow_mut::<T>() {
+            Ok(storage) => Some(storage),
+            Err(BorrowError::MissingStorage(_)) => None,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Like [`World::borrow`], but fails instead of panicking.
+    pub fn try_borrow<T: Component + StoreComponentsIn>(&self) -> Result<StorageRef<'_, T::Storage>, BorrowError> {
+        self.components.try_borrow_storage::<T, T::Storage>()
+    }
+
+    /// Like [`World::borrow_mut`], but fails instead of panicking.
+    pub fn try_borrow_mut<T: Component + StoreComponentsIn>(&self) -> Result<StorageRefMut<'_, T::Storage>, BorrowError> {
+        self.components.try_borrow_storage_mut::<T, T::Storage>()
+    }
+
     pub fn entity_types(&self) -> &[EntityType] {
         &self.entity_types
     }
 
-    pub fn entry(&mut self, entity: Entity) -> Option<Entry> {
+    /// Identifies the world together with the numbering of its entity types.
+    /// Entity types are only ever appended, unless they are renumbered, which
+    /// gives the world a new epoch. Anything caching entity type indices can
+    /// tell from the epoch when its cache is stale.
+    pub fn layout_epoch(&self) -> u64 {
+        self.layout_epoch
+    }
+
+    /// Use `storage` to store its component type, instead of the default storage
+    /// of the component. This must be called before any entity with the
+    /// component is pushed into the world, and returns `false` otherwise. The
+    /// storage allocates from the world's allocator, and its columns are aligned
+    /// like the world aligns the columns of the component type.
+    pub fn register_storage<S: OpaqueComponentStorage>(&mut self, mut storage: S) -> bool {
+        let alignment = cmp::max(storage.alignment(), self.alignment_of(storage.component_type()));
+        storage.set_allocator(self.allocator.clone());
+        storage.set_alignment(alignment);
+
+        self.components.insert(Box::new(storage))
+    }
+
+    pub fn sparse_components(&self) -> &SparseComponentMap {
+        &self.sparse_components
+    }
+
+    pub fn sparse_components_mut(&mut self) -> &mut SparseComponentMap {
+        &mut self.sparse_components
+    }
+
+    pub fn relations(&self) -> &RelationMap {
+        &self.relations
+    }
+
+    pub fn relations_mut(&mut self) -> &mut RelationMap {
+        &mut self.relations
+    }
+
+    /// Attach a sparse set component to an entity. Sparse set components are
+    /// stored outside of the entity's entity type, so attaching or detaching them
+    /// does not move the entity's other components.
+    ///
+    /// Returns `false` if the entity does not exist, in which case the component
+    /// is dropped.
+    pub fn insert_sparse<T: Component>(&mut self, entity: Entity, component: T) -> bool {
+        if !self.entities.contains(entity) {
+            return false;
+        }
+
+        self.sparse_components.get_or_insert::<T>().insert(entity, component);
+
+        true
+    }
+
+    /// Detach a sparse set component from an entity, returning it if the entity had one.
+    pub fn remove_sparse<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        self.sparse_components
+            .get_mut::<T>()
+            .and_then(|storage| storage.remove(entity))
+    }
+
+    pub fn get_sparse<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.sparse_components
+            .get::<T>()
+            .and_then(|storage| storage.get(entity))
+    }
+
+    pub fn get_sparse_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.sparse_components
+            .get_mut::<T>()
+            .and_then(|storage| storage.get_mut(entity))
+    }
+
+    pub fn has_sparse<T: Component>(&self, entity: Entity) -> bool {
+        self.sparse_components
+            .get::<T>()
+            .is_some_and(|storage| storage.contains(entity))
+    }
+
+    pub fn entry(&mut self, entity: Entity) -> Option<Entry<'_>> {
         self.entities
             .get(entity)
             .map(move |location| Entry::new(location, self))
@@ -626,6 +1823,12 @@ impl World {
 }
 
 
+impl Default for World {
+    fn default() -> World {
+        World::new()
+    }
+}
+
 impl<T> StoreComponentsIn for T
 where
     T: Component,
@@ -1,3 +1,6 @@
+#![allow(clippy::redundant_field_names)]
+#![allow(dead_code)]
+
 extern crate realm;
 
 
@@ -15,6 +18,7 @@ fn test_empty_entity_storage_has_zero_length() {
     assert_eq!(world.len(), 0);
 }
 
+#[derive(Clone, serde::Deserialize)]
 struct Position {
     x: f32,
     y: f32,
@@ -184,3 +188,1451 @@ fn test_entity_storage_push_multiple_elements_remove() {
     assert!(world.is_empty());
 }
 
+
+#[test]
+fn test_entity_storage_push_different_layouts_sharing_components() {
+    let mut world = realm::World::new();
+    let entity0 = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(0_f32, 0_f32, 0_f32)
+    ));
+    let entity1 = world.push((
+        Position::new(1_f32, 1_f32, 1_f32),
+        Acceleration::new(1_f32, 1_f32, 1_f32)
+    ));
+
+    assert_eq!(world.entry(entity0).unwrap().get_component::<Position>().unwrap().x, 0_f32);
+    assert_eq!(world.entry(entity1).unwrap().get_component::<Position>().unwrap().x, 1_f32);
+    assert!(world.has_component::<Acceleration>(entity1));
+    assert!(!world.has_component::<Velocity>(entity1));
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Stunned(u32);
+
+
+#[test]
+fn test_insert_sparse_component() {
+    let mut world = realm::World::new();
+    let entity = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(1_f32, 1_f32, 1_f32)
+    ));
+
+    assert!(!world.has_sparse::<Stunned>(entity));
+    assert!(world.insert_sparse(entity, Stunned(3)));
+    assert!(world.has_sparse::<Stunned>(entity));
+    assert_eq!(world.get_sparse::<Stunned>(entity), Some(&Stunned(3)));
+    assert!(world.has_component::<Position>(entity));
+    assert!(!world.has_component::<Stunned>(entity));
+}
+
+#[test]
+fn test_insert_sparse_component_missing_entity() {
+    let mut world = realm::World::new();
+    let entity = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(1_f32, 1_f32, 1_f32)
+    ));
+    world.remove(entity);
+
+    assert!(!world.insert_sparse(entity, Stunned(3)));
+    assert!(!world.has_sparse::<Stunned>(entity));
+}
+
+#[test]
+fn test_remove_sparse_component() {
+    let mut world = realm::World::new();
+    let entity = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(1_f32, 1_f32, 1_f32)
+    ));
+    world.insert_sparse(entity, Stunned(3));
+
+    assert_eq!(world.remove_sparse::<Stunned>(entity), Some(Stunned(3)));
+    assert_eq!(world.remove_sparse::<Stunned>(entity), None);
+    assert!(world.contains(entity));
+}
+
+#[test]
+fn test_remove_entity_removes_sparse_components() {
+    let mut world = realm::World::new();
+    let entity = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(1_f32, 1_f32, 1_f32)
+    ));
+    world.insert_sparse(entity, Stunned(3));
+    world.remove(entity);
+
+    assert!(world.sparse_components().get::<Stunned>().unwrap().is_empty());
+}
+
+#[test]
+fn test_join_entity_type_with_sparse_components() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..4)
+        .map(|i| world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        )))
+        .collect();
+    world.insert_sparse(entities[1], Stunned(1));
+    world.insert_sparse(entities[3], Stunned(3));
+
+    let entity_type = &world.entity_types()[0];
+    let stunned = world.sparse_components().get::<Stunned>().unwrap();
+    let mut result = Vec::new();
+    for (index, component) in stunned.join(entity_type.entities()) {
+        let entity = entity_type.entities()[index.id()];
+        result.push((entity, component.0));
+    }
+
+    assert_eq!(result, vec![(entities[1], 1), (entities[3], 3)]);
+}
+
+#[test]
+fn test_compact_world_after_removal() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..64)
+        .map(|i| world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        )))
+        .collect();
+    for entity in entities[1..].iter() {
+        world.remove(*entity);
+    }
+    world.compact();
+
+    assert_eq!(world.entity_types()[0].occupancy(), 1_f32);
+    assert!(world.contains(entities[0]));
+    assert_eq!(world.entry(entities[0]).unwrap().get_component::<Position>().unwrap().x, 0_f32);
+}
+
+#[test]
+fn test_compaction_policy_occupancy() {
+    let mut world = realm::World::new();
+    world.set_compaction_policy(realm::CompactionPolicy::Occupancy(0.25));
+    let entities: Vec<realm::Entity> = (0..64)
+        .map(|i| world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        )))
+        .collect();
+    for entity in entities[..60].iter() {
+        world.remove(*entity);
+    }
+
+    assert!(world.entity_types()[0].occupancy() >= 0.25);
+    for (i, entity) in entities[60..].iter().enumerate() {
+        let position = world.entry(*entity).unwrap().get_component::<Position>().unwrap().x;
+        assert_eq!(position, (60 + i) as f32);
+    }
+}
+
+#[test]
+fn test_remove_empty_entity_types() {
+    let mut world = realm::World::new();
+    let entity0 = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(0_f32, 0_f32, 0_f32)
+    ));
+    let entity1 = world.push((
+        Position::new(1_f32, 1_f32, 1_f32),
+        Acceleration::new(1_f32, 1_f32, 1_f32)
+    ));
+    world.remove(entity0);
+
+    assert_eq!(world.entity_types().len(), 2);
+    assert_eq!(world.remove_empty_entity_types(), 1);
+    assert_eq!(world.entity_types().len(), 1);
+    assert_eq!(world.remove_empty_entity_types(), 0);
+
+    let mut entry = world.entry(entity1).unwrap();
+    assert_eq!(entry.entity_type().entities(), &[entity1]);
+    assert_eq!(entry.get_component::<Position>().unwrap().x, 1_f32);
+    assert_eq!(entry.get_component_mut::<Acceleration>().unwrap().x, 1_f32);
+}
+
+#[test]
+fn test_push_after_remove_empty_entity_types() {
+    let mut world = realm::World::new();
+    let entity0 = world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(0_f32, 0_f32, 0_f32)
+    ));
+    let entity1 = world.push((
+        Position::new(1_f32, 1_f32, 1_f32),
+        Acceleration::new(1_f32, 1_f32, 1_f32)
+    ));
+    world.remove(entity0);
+    world.remove_empty_entity_types();
+    let entity2 = world.push((
+        Position::new(2_f32, 2_f32, 2_f32),
+        Velocity::new(2_f32, 2_f32, 2_f32)
+    ));
+
+    assert_eq!(world.entity_types().len(), 2);
+    assert_eq!(world.entry(entity1).unwrap().get_component::<Position>().unwrap().x, 1_f32);
+    assert_eq!(world.entry(entity2).unwrap().get_component::<Position>().unwrap().x, 2_f32);
+    assert_eq!(world.entry(entity2).unwrap().get_component::<Velocity>().unwrap().x, 2_f32);
+}
+
+#[test]
+fn test_memory_stats() {
+    let mut world = realm::World::new();
+    for i in 0..3 {
+        world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        ));
+    }
+    let stats = world.memory_stats();
+    let position_size = std::mem::size_of::<Position>();
+
+    assert_eq!(stats.entity_types().len(), 1);
+    assert_eq!(stats.entity_types()[0].entity_count(), 3);
+    assert_eq!(stats.entity_types()[0].components().len(), 2);
+    assert_eq!(stats.components().len(), 2);
+
+    let position = stats.components()
+        .iter()
+        .find(|component| component.name().ends_with("Position"))
+        .unwrap()
+        .usage();
+
+    assert_eq!(position.length(), 3);
+    assert!(position.capacity() >= 3);
+    assert_eq!(position.capacity_bytes(), position.capacity() * position_size);
+    assert_eq!(position.wasted_bytes(), (position.capacity() - 3) * position_size);
+}
+
+#[test]
+fn test_memory_stats_after_compact_has_no_waste() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..16)
+        .map(|i| world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        )))
+        .collect();
+    for entity in entities[..10].iter() {
+        world.remove(*entity);
+    }
+
+    assert!(world.memory_stats().total().wasted_bytes() > 0);
+
+    world.compact();
+    let stats = world.memory_stats();
+
+    assert_eq!(stats.total().wasted_bytes(), 0);
+    assert_eq!(stats.entity_types()[0].total().length(), 3 * 6);
+}
+
+#[test]
+fn test_register_chunked_storage() {
+    let mut world = realm::World::new();
+
+    assert!(world.register_storage(realm::ChunkedStorage::<Position>::with_chunk_size(64)));
+    assert!(!world.register_storage(realm::ChunkedStorage::<Position>::new()));
+
+    let entities: Vec<realm::Entity> = (0..16)
+        .map(|i| world.push((
+            Position::new(i as f32, i as f32, i as f32),
+            Velocity::new(1_f32, 1_f32, 1_f32)
+        )))
+        .collect();
+    world.remove(entities[3]);
+
+    for (i, entity) in entities.iter().enumerate().filter(|(i, _)| *i != 3) {
+        let mut entry = world.entry(*entity).unwrap();
+        assert_eq!(entry.get_component::<Position>().unwrap().x, i as f32);
+        entry.get_component_mut::<Position>().unwrap().x += 1_f32;
+    }
+
+    let entity_type = world.entry(entities[0]).unwrap().location().entity_type();
+    let storage = world
+        .components()
+        .get_storage::<Position, realm::ChunkedStorage<Position>>()
+        .unwrap();
+    let chunk_lengths: Vec<usize> = storage.chunks(entity_type).map(|chunk| chunk.len()).collect();
+
+    assert_eq!(storage.chunk_capacity(), 5);
+    assert_eq!(chunk_lengths, vec![5, 5, 5]);
+    assert_eq!(world.memory_stats().entity_types()[0].entity_count(), 15);
+}
+
+#[test]
+fn test_registered_storage_uses_world_allocator_and_alignment() {
+    use realm::ComponentTypeIndex;
+    use std::sync::Arc;
+
+    let allocator = Arc::new(realm::TrackingAllocator::new(realm::GlobalAllocator));
+    {
+        let mut world = realm::World::with_allocator(allocator.clone());
+        world.set_column_alignment(64);
+        assert!(world.register_storage(realm::ChunkedStorage::<Position>::with_chunk_size(64)));
+        let entity = world.push((Position::new(1_f32, 2_f32, 3_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+        let position = world.entry(entity).unwrap().get_component::<Position>().unwrap() as *const Position;
+
+        assert_eq!(position as usize % 64, 0);
+        assert!(allocator.allocated_bytes(ComponentTypeIndex::of::<Position>()) > 0);
+    }
+
+    assert_eq!(allocator.total_allocated_bytes(), 0);
+}
+
+#[test]
+fn test_register_storage_after_use() {
+    let mut world = realm::World::new();
+    world.push((
+        Position::new(0_f32, 0_f32, 0_f32),
+        Velocity::new(1_f32, 1_f32, 1_f32)
+    ));
+
+    assert!(!world.register_storage(realm::ChunkedStorage::<Position>::new()));
+}
+
+#[test]
+fn test_world_with_tracking_allocator() {
+    use realm::ComponentTypeIndex;
+    use std::sync::Arc;
+
+    let allocator = Arc::new(realm::TrackingAllocator::new(realm::GlobalAllocator));
+    {
+        let mut world = realm::World::with_allocator(allocator.clone());
+        let entities: Vec<realm::Entity> = (0..8)
+            .map(|i| world.push((
+                Position::new(i as f32, i as f32, i as f32),
+                Velocity::new(1_f32, 1_f32, 1_f32)
+            )))
+            .collect();
+        let position_bytes = allocator.allocated_bytes(ComponentTypeIndex::of::<Position>());
+
+        assert_eq!(position_bytes, 8 * std::mem::size_of::<Position>());
+        assert_eq!(allocator.allocated_bytes(ComponentTypeIndex::of::<Acceleration>()), 0);
+
+        for entity in entities[2..].iter() {
+            world.remove(*entity);
+        }
+        world.compact();
+
+        assert_eq!(
+            allocator.allocated_bytes(ComponentTypeIndex::of::<Position>()), 
+            2 * std::mem::size_of::<Position>()
+        );
+    }
+
+    assert_eq!(allocator.total_allocated_bytes(), 0);
+}
+
+#[test]
+fn test_world_over_aligned_columns() {
+    let mut world = realm::World::new();
+    world.set_column_alignment(32);
+
+    assert!(world.set_component_alignment::<Position>(64));
+
+    let entity = world.push((Position::new(1_f32, 2_f32, 3_f32), Velocity::new(4_f32, 5_f32, 6_f32)));
+    let entry = world.entry(entity).unwrap();
+    let position = entry.get_component::<Position>().unwrap() as *const Position;
+    let velocity = entry.get_component::<Velocity>().unwrap() as *const Velocity;
+
+    assert_eq!(position as usize % 64, 0);
+    assert_eq!(velocity as usize % 32, 0);
+    assert!(!world.set_component_alignment::<Position>(128));
+}
+
+#[test]
+fn test_merge_worlds_reallocates_conflicting_entities() {
+    let mut world = realm::World::new();
+    let existing = world.push((Position::new(-1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut staging = realm::World::new();
+    let moved: Vec<realm::Entity> = (0..3)
+        .map(|i| staging.push((
+            Position::new(i as f32, 0_f32, 0_f32), 
+            Acceleration::new(0_f32, 0_f32, 0_f32)
+        )))
+        .collect();
+    let moved_with_velocity = staging.push((
+        Position::new(3_f32, 0_f32, 0_f32), 
+        Velocity::new(0_f32, 0_f32, 0_f32)
+    ));
+    staging.insert_sparse(moved[1], Stunned(7));
+    let entity_map = world.merge_from(&mut staging);
+
+    assert!(staging.is_empty());
+    assert_eq!(world.len(), 5);
+    assert_eq!(entity_map.len(), 4);
+    assert_ne!(entity_map[&moved[0]], existing);
+    assert_eq!(world.entry(existing).unwrap().get_component::<Position>().unwrap().x, -1_f32);
+    for (i, entity) in moved.iter().chain(Some(&moved_with_velocity)).enumerate() {
+        let new_entity = entity_map[entity];
+        let entry = world.entry(new_entity).unwrap();
+
+        assert_eq!(entry.get_component::<Position>().unwrap().x, i as f32);
+    }
+    assert!(world.has_component::<Velocity>(entity_map[&moved_with_velocity]));
+    assert!(world.has_component::<Acceleration>(entity_map[&moved[2]]));
+    assert_eq!(world.get_sparse::<Stunned>(entity_map[&moved[1]]), Some(&Stunned(7)));
+}
+
+#[test]
+fn test_merge_worlds_keeps_source_columns_configured() {
+    use realm::ComponentTypeIndex;
+    use std::sync::Arc;
+
+    let allocator = Arc::new(realm::TrackingAllocator::new(realm::GlobalAllocator));
+    let mut world = realm::World::new();
+    let mut staging = realm::World::with_allocator(allocator.clone());
+    assert!(staging.set_component_alignment::<Position>(64));
+    staging.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    world.merge_from(&mut staging);
+    let merged_bytes = allocator.allocated_bytes(ComponentTypeIndex::of::<Position>());
+    let entity = staging.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let position = staging.entry(entity).unwrap().get_component::<Position>().unwrap() as *const Position;
+
+    assert_eq!(position as usize % 64, 0);
+    assert!(allocator.allocated_bytes(ComponentTypeIndex::of::<Position>()) > merged_bytes);
+}
+
+#[test]
+fn test_merge_worlds_keeps_free_entities() {
+    let mut world = realm::World::new();
+    let mut staging = realm::World::new();
+    let moved: Vec<realm::Entity> = (0..2)
+        .map(|i| staging.push((
+            Position::new(i as f32, 0_f32, 0_f32), 
+            Velocity::new(0_f32, 0_f32, 0_f32)
+        )))
+        .collect();
+    let entity_map = world.merge_from(&mut staging);
+    let pushed = world.push((Position::new(2_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+
+    assert_eq!(entity_map[&moved[0]], moved[0]);
+    assert_eq!(entity_map[&moved[1]], moved[1]);
+    assert!(!moved.contains(&pushed));
+    assert_eq!(world.len(), 3);
+}
+
+#[derive(Debug, PartialEq)]
+struct Target(realm::Entity);
+
+impl realm::MapEntities for Target {
+    fn map_entities(&mut self, entity_map: &std::collections::HashMap<realm::Entity, realm::Entity>) {
+        if let Some(entity) = entity_map.get(&self.0) {
+            self.0 = *entity;
+        }
+    }
+}
+
+#[test]
+fn test_merge_worlds_maps_entities() {
+    let mut world = realm::World::new();
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    world.register_map_entities::<Target>();
+    let mut staging = realm::World::new();
+    let target = staging.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let follower = staging.push((Position::new(2_f32, 0_f32, 0_f32), Target(target)));
+    let sparse_follower = staging.push((Position::new(3_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    staging.insert_sparse(sparse_follower, Target(follower));
+    let entity_map = world.merge_from(&mut staging);
+    let new_target = entity_map[&target];
+    let new_follower = entity_map[&follower];
+
+    assert_ne!(new_target, target);
+    assert_eq!(
+        world.entry(new_follower).unwrap().get_component::<Target>().unwrap(), 
+        &Target(new_target)
+    );
+    assert_eq!(world.get_sparse::<Target>(entity_map[&sparse_follower]), Some(&Target(new_follower)));
+}
+
+#[test]
+fn test_universe_worlds_allocate_unique_entities() {
+    let universe = realm::Universe::with_block_size(4);
+    let mut world0 = universe.create_world();
+    let mut world1 = universe.create_world();
+    let entities0: Vec<realm::Entity> = (0..6)
+        .map(|i| world0.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))))
+        .collect();
+    let entities1: Vec<realm::Entity> = (0..6)
+        .map(|i| world1.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))))
+        .collect();
+
+    assert!(entities0.iter().all(|entity| !entities1.contains(entity)));
+
+    let entity_map = world0.merge_from(&mut world1);
+
+    assert!(entity_map.iter().all(|(entity, new_entity)| entity == new_entity));
+    assert_eq!(world0.len(), 12);
+
+    // The merged ids belong to the destination world now, so the source world 
+    // must not hand them out again.
+    let pushed = world1.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+
+    assert!(!world0.contains(pushed));
+}
+
+#[test]
+fn test_universe_worlds_on_threads() {
+    let universe = realm::Universe::with_block_size(8);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let universe = universe.clone();
+            std::thread::spawn(move || {
+                let mut world = universe.create_world();
+                (0..20)
+                    .map(|i| world.push((
+                        Position::new(i as f32, 0_f32, 0_f32), 
+                        Velocity::new(0_f32, 0_f32, 0_f32)
+                    )))
+                    .collect::<Vec<realm::Entity>>()
+            })
+        })
+        .collect();
+    let mut entities: Vec<realm::Entity> = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+    entities.sort_by_key(|entity| entity.id());
+    entities.dedup();
+
+    assert_eq!(entities.len(), 80);
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct Name(String);
+
+#[test]
+fn test_clone_entity() {
+    let mut world = realm::World::new();
+    world.register_clone::<Name>();
+    world.register_clone::<Position>();
+    world.register_clone::<Stunned>();
+    let entity = world.push((Position::new(1_f32, 2_f32, 3_f32), Name(String::from("original"))));
+    world.insert_sparse(entity, Stunned(3));
+    let clone = world.clone_entity(entity).unwrap();
+    world.entry(entity).unwrap().get_component_mut::<Name>().unwrap().0.push_str(" changed");
+
+    assert_ne!(clone, entity);
+    assert_eq!(world.len(), 2);
+    assert_eq!(world.entry(clone).unwrap().get_component::<Position>().unwrap().y, 2_f32);
+    assert_eq!(world.entry(clone).unwrap().get_component::<Name>().unwrap(), &Name(String::from("original")));
+    assert_eq!(world.get_sparse::<Stunned>(clone), Some(&Stunned(3)));
+}
+
+#[test]
+fn test_clone_entity_without_clone_registration() {
+    let mut world = realm::World::new();
+    world.register_clone::<Position>();
+    let entity = world.push((Position::new(1_f32, 2_f32, 3_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+
+    assert_eq!(world.clone_entity(entity), None);
+    assert_eq!(world.len(), 1);
+    assert!(world.clone_world().is_none());
+}
+
+#[test]
+fn test_clone_world() {
+    let mut world = realm::World::new();
+    world.register_clone::<Name>();
+    world.register_clone::<Position>();
+    world.register_clone::<Stunned>();
+    let entities: Vec<realm::Entity> = (0..4)
+        .map(|i| world.push((Position::new(i as f32, 0_f32, 0_f32), Name(format!("entity {}", i)))))
+        .collect();
+    world.insert_sparse(entities[2], Stunned(2));
+    let mut clone = world.clone_world().unwrap();
+    clone.remove(entities[0]);
+    clone.entry(entities[1]).unwrap().get_component_mut::<Name>().unwrap().0.clear();
+
+    assert_eq!(world.len(), 4);
+    assert_eq!(clone.len(), 3);
+    assert_eq!(world.entry(entities[1]).unwrap().get_component::<Name>().unwrap(), &Name(String::from("entity 1")));
+    assert_eq!(clone.entry(entities[3]).unwrap().get_component::<Position>().unwrap().x, 3_f32);
+    assert_eq!(clone.get_sparse::<Stunned>(entities[2]), Some(&Stunned(2)));
+}
+
+#[test]
+fn test_clone_world_in_universe_allocates_disjoint_entities() {
+    let universe = realm::Universe::with_block_size(4);
+    let mut world = universe.create_world();
+    world.register_clone::<Position>();
+    world.register_clone::<Name>();
+    let spawn = |world: &mut realm::World, i: usize| {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Name(format!("entity {}", i))))
+    };
+    let entity = spawn(&mut world, 0);
+    let mut clone = world.clone_world().unwrap();
+
+    assert!(clone.contains(entity));
+
+    let created: Vec<realm::Entity> = (1..6).map(|i| spawn(&mut world, i)).collect();
+    let cloned: Vec<realm::Entity> = (1..6).map(|i| spawn(&mut clone, i)).collect();
+
+    assert!(created.iter().all(|entity| !cloned.contains(entity) && !clone.contains(*entity)));
+    assert!(cloned.iter().all(|entity| !world.contains(*entity)));
+}
+
+#[test]
+fn test_spawn_prefab() {
+    let mut world = realm::World::new();
+    let prefab = realm::Prefab::new()
+        .with(Position::new(1_f32, 2_f32, 3_f32))
+        .with(Name(String::from("goblin")));
+    let entity = world.spawn_prefab(&prefab);
+    let pushed = world.push((Position::new(0_f32, 0_f32, 0_f32), Name(String::from("orc"))));
+
+    assert_eq!(world.entry(entity).unwrap().get_component::<Position>().unwrap().z, 3_f32);
+    assert_eq!(world.entry(entity).unwrap().get_component::<Name>().unwrap(), &Name(String::from("goblin")));
+    let entity_type = world.entry(entity).unwrap().location().entity_type();
+
+    assert_eq!(world.entry(pushed).unwrap().location().entity_type(), entity_type);
+}
+
+#[test]
+fn test_spawn_prefabs_with_children() {
+    let mut world = realm::World::new();
+    let prefab = realm::Prefab::new()
+        .with(Name(String::from("squad")))
+        .with(Name(String::from("platoon")))
+        .with_child(realm::Prefab::new().with(Position::new(1_f32, 0_f32, 0_f32)))
+        .with_child(realm::Prefab::new().with(Position::new(2_f32, 0_f32, 0_f32)));
+    let entities = world.spawn_prefabs(&prefab, 3);
+
+    assert_eq!(entities.len(), 3);
+    assert_eq!(world.len(), 9);
+    for entity in entities.iter() {
+        let entry = world.entry(*entity).unwrap();
+
+        assert_eq!(entry.get_component::<Name>().unwrap(), &Name(String::from("platoon")));
+        assert!(!world.has_component::<Position>(*entity));
+    }
+}
+
+#[cfg(feature = "serialize")]
+#[test]
+fn test_load_prefab_from_data() {
+    let mut world = realm::World::new();
+    world.register_deserialize::<Position>("position");
+    world.register_deserialize::<Name>("name");
+    let data = r#"{
+        "components": { "name": "squad", "position": { "x": 1.0, "y": 2.0, "z": 3.0 } },
+        "children": [{ "components": { "name": "scout" } }]
+    }"#;
+    let prefab = realm::Prefab::load(world.registry(), &mut serde_json::Deserializer::from_str(data)).unwrap();
+    let entity = world.spawn_prefab(&prefab);
+    let entry = world.entry(entity).unwrap();
+
+    assert_eq!(entry.get_component::<Name>().unwrap(), &Name(String::from("squad")));
+    assert_eq!(entry.get_component::<Position>().unwrap().z, 3_f32);
+    assert_eq!(world.len(), 2);
+    assert!(prefab.children()[0].contains_component::<Name>());
+
+    let unknown = r#"{ "components": { "velocity": [0.0, 0.0, 0.0] } }"#;
+    let error = realm::Prefab::load(world.registry(), &mut serde_json::Deserializer::from_str(unknown));
+
+    assert!(error.is_err());
+}
+
+fn spawn_node(world: &mut realm::World, x: f32) -> realm::Entity {
+    world.push((Position::new(x, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)))
+}
+
+#[test]
+fn test_remove_recursive_removes_subtree() {
+    let mut world = realm::World::new();
+    let root = spawn_node(&mut world, 0_f32);
+    let child0 = spawn_node(&mut world, 1_f32);
+    let child1 = spawn_node(&mut world, 2_f32);
+    let grandchild = spawn_node(&mut world, 3_f32);
+    let other = spawn_node(&mut world, 4_f32);
+    world.set_parent(child0, root);
+    world.set_parent(child1, root);
+    world.set_parent(grandchild, child0);
+
+    assert_eq!(world.descendants(root), vec![child0, grandchild, child1]);
+    assert_eq!(world.remove_recursive(child0), 2);
+    assert_eq!(world.children(root), &[child1]);
+    assert_eq!(world.remove_recursive(root), 2);
+    assert_eq!(world.len(), 1);
+    assert!(world.contains(other));
+}
+
+#[test]
+fn test_remove_recursive_counts_entities_removed_with_relation_targets_once() {
+    let mut world = realm::World::new();
+    world.set_relation_cleanup::<DockedAt>(realm::RelationCleanup::RemoveSource);
+    let root = spawn_node(&mut world, 0_f32);
+    let station = spawn_node(&mut world, 1_f32);
+    let ship = spawn_node(&mut world, 2_f32);
+    world.set_parent(station, root);
+    world.set_parent(ship, root);
+    world.add_relation(ship, DockedAt, station);
+
+    assert_eq!(world.remove_recursive(root), 2);
+    assert!(world.is_empty());
+    assert_eq!(world.remove_recursive(root), 0);
+}
+
+#[test]
+fn test_remove_unlinks_hierarchy() {
+    let mut world = realm::World::new();
+    let root = spawn_node(&mut world, 0_f32);
+    let child = spawn_node(&mut world, 1_f32);
+    let grandchild = spawn_node(&mut world, 2_f32);
+    world.set_parent(child, root);
+    world.set_parent(grandchild, child);
+    world.remove(child);
+
+    assert_eq!(world.children(root), &[]);
+    assert_eq!(world.parent(grandchild), None);
+    assert!(world.contains(grandchild));
+}
+
+#[test]
+fn test_merge_worlds_maps_hierarchy() {
+    let mut world = realm::World::new();
+    spawn_node(&mut world, 0_f32);
+    let mut staging = realm::World::new();
+    let root = spawn_node(&mut staging, 1_f32);
+    let child = spawn_node(&mut staging, 2_f32);
+    staging.set_parent(child, root);
+    let entity_map = world.merge_from(&mut staging);
+
+    assert_eq!(world.parent(entity_map[&child]), Some(entity_map[&root]));
+    assert_eq!(world.children(entity_map[&root]), &[entity_map[&child]]);
+}
+
+#[test]
+fn test_spawn_prefab_links_children() {
+    let mut world = realm::World::new();
+    let prefab = realm::Prefab::new()
+        .with(Name(String::from("ship")))
+        .with_child(realm::Prefab::new().with(Name(String::from("turret"))));
+    let ships = world.spawn_prefabs(&prefab, 2);
+
+    for ship in ships.iter() {
+        let turrets = world.children(*ship).to_vec();
+
+        assert_eq!(turrets.len(), 1);
+        assert_eq!(world.parent(turrets[0]), Some(*ship));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Likes(u32);
+
+#[derive(Clone, Debug, PartialEq)]
+struct DockedAt;
+
+#[test]
+fn test_relations_reverse_lookup_and_cleanup() {
+    let mut world = realm::World::new();
+    let alice = spawn_node(&mut world, 0_f32);
+    let bob = spawn_node(&mut world, 1_f32);
+    let carol = spawn_node(&mut world, 2_f32);
+    world.add_relation(alice, Likes(3), carol);
+    world.add_relation(bob, Likes(5), carol);
+    world.add_relation(carol, Likes(1), alice);
+
+    assert_eq!(world.relation_sources::<Likes>(carol), &[alice, bob]);
+    assert_eq!(world.get_relation::<Likes>(bob, carol), Some(&Likes(5)));
+    assert_eq!(world.relation_targets::<Likes>(carol), vec![alice]);
+
+    world.remove(carol);
+
+    assert!(world.contains(alice));
+    assert!(world.contains(bob));
+    assert!(world.relation_targets::<Likes>(alice).is_empty());
+    assert!(world.relation_sources::<Likes>(alice).is_empty());
+    assert!(world.relations().get::<Likes>().unwrap().is_empty());
+}
+
+#[test]
+fn test_relations_remove_sources_with_target() {
+    let mut world = realm::World::new();
+    world.set_relation_cleanup::<DockedAt>(realm::RelationCleanup::RemoveSource);
+    let station = spawn_node(&mut world, 0_f32);
+    let ship0 = spawn_node(&mut world, 1_f32);
+    let ship1 = spawn_node(&mut world, 2_f32);
+    let other = spawn_node(&mut world, 3_f32);
+    world.add_relation(ship0, DockedAt, station);
+    world.add_relation(ship1, DockedAt, station);
+    world.remove(station);
+
+    assert_eq!(world.len(), 1);
+    assert!(world.contains(other));
+}
+
+#[test]
+fn test_relations_remove_long_chains_of_sources() {
+    let mut world = realm::World::new();
+    world.set_relation_cleanup::<DockedAt>(realm::RelationCleanup::RemoveSource);
+    let first = spawn_node(&mut world, 0_f32);
+    let mut target = first;
+    for i in 1..20_000 {
+        let source = spawn_node(&mut world, i as f32);
+        world.add_relation(source, DockedAt, target);
+        target = source;
+    }
+    let other = spawn_node(&mut world, -1_f32);
+
+    assert!(world.remove(first));
+    assert_eq!(world.len(), 1);
+    assert!(world.contains(other));
+}
+
+#[test]
+fn test_merge_worlds_maps_relations() {
+    let mut world = realm::World::new();
+    spawn_node(&mut world, 0_f32);
+    let mut staging = realm::World::new();
+    let station = spawn_node(&mut staging, 1_f32);
+    let ship = spawn_node(&mut staging, 2_f32);
+    staging.add_relation(ship, DockedAt, station);
+    let entity_map = world.merge_from(&mut staging);
+
+    assert_eq!(world.relation_sources::<DockedAt>(entity_map[&station]), &[entity_map[&ship]]);
+}
+
+#[cfg(feature = "transform")]
+#[test]
+fn test_propagate_transforms() {
+    use realm::transform::{
+        GlobalTransform,
+        LocalTransform,
+        propagate_transforms,
+    };
+
+    let mut world = realm::World::new();
+    let root = world.push((
+        LocalTransform::from_translation([1_f32, 0_f32, 0_f32]).with_scale([2_f32, 2_f32, 2_f32]), 
+        GlobalTransform::default()
+    ));
+    let child = world.push((
+        LocalTransform::from_translation([0_f32, 1_f32, 0_f32]), 
+        GlobalTransform::default()
+    ));
+    let grandchild = world.push((
+        LocalTransform::from_translation([0_f32, 0_f32, 1_f32]), 
+        GlobalTransform::default()
+    ));
+    world.set_parent(grandchild, child);
+    world.set_parent(child, root);
+    propagate_transforms(&mut world);
+    let global = |world: &mut realm::World, entity| {
+        world.entry(entity).unwrap().get_component::<GlobalTransform>().unwrap().translation()
+    };
+
+    assert_eq!(global(&mut world, root), [1_f32, 0_f32, 0_f32]);
+    assert_eq!(global(&mut world, child), [1_f32, 2_f32, 0_f32]);
+    assert_eq!(global(&mut world, grandchild), [1_f32, 2_f32, 2_f32]);
+}
+
+#[test]
+fn test_multi_view_rejects_conflicting_claims() {
+    let mut world = realm::World::new();
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut view = world.components_mut().get_multi_view_mut();
+
+    assert!(view.claim::<Position>().is_ok());
+    assert!(matches!(view.claim::<Position>(), Err(realm::ClaimError::AlreadyClaimed(_))));
+    assert!(matches!(view.claim_ref::<Position>(), Err(realm::ClaimError::AlreadyClaimed(_))));
+    assert!(view.claim_ref::<Velocity>().is_ok());
+    assert!(view.claim_ref::<Velocity>().is_ok());
+    assert!(matches!(view.claim_opaque::<Velocity>(), Err(realm::ClaimError::AlreadyClaimed(_))));
+    assert!(matches!(view.claim::<Acceleration>(), Err(realm::ClaimError::MissingStorage(_))));
+    assert!(!view.is_claimed::<Acceleration>());
+}
+
+#[test]
+fn test_multi_view_rejects_mismatched_storage() {
+    let mut world = realm::World::new();
+    world.register_storage(realm::ChunkedStorage::<Position>::new());
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut view = world.components_mut().get_multi_view_mut();
+
+    assert!(matches!(view.claim::<Position>(), Err(realm::ClaimError::StorageMismatch(_))));
+    assert!(matches!(view.claim_ref::<Position>(), Err(realm::ClaimError::StorageMismatch(_))));
+    assert!(!view.is_claimed::<Position>());
+    assert!(view.claim_opaque::<Position>().is_ok());
+}
+
+#[test]
+fn test_borrow_detects_conflicts() {
+    let mut world = realm::World::new();
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let positions0 = world.borrow::<Position>().unwrap();
+    let positions1 = world.borrow::<Position>().unwrap();
+    let velocities = world.borrow_mut::<Velocity>().unwrap();
+
+    assert!(matches!(world.try_borrow_mut::<Position>(), Err(realm::BorrowError::AlreadyBorrowed(_))));
+    assert!(matches!(world.try_borrow::<Velocity>(), Err(realm::BorrowError::AlreadyBorrowed(_))));
+    assert!(matches!(world.try_borrow::<Acceleration>(), Err(realm::BorrowError::MissingStorage(_))));
+
+    drop(positions0);
+    drop(positions1);
+    drop(velocities);
+
+    assert!(world.try_borrow_mut::<Position>().is_ok());
+    assert!(world.try_borrow::<Velocity>().is_ok());
+}
+
+#[test]
+fn test_borrow_storages_from_several_threads() {
+    let mut world = realm::World::new();
+    world.register_storage(realm::ChunkedStorage::<Position>::new());
+    world.register_storage(realm::ChunkedStorage::<Velocity>::new());
+    let entity = world.push((Position::new(1_f32, 2_f32, 3_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let entity_type = world.entry(entity).unwrap().location().entity_type();
+    let world = &world;
+
+    std::thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(move || {
+                let positions = world.components()
+                    .try_borrow_storage::<Position, realm::ChunkedStorage<Position>>()
+                    .unwrap();
+                let sum: f32 = positions.chunks(entity_type).flatten().map(|position| position.x).sum();
+
+                assert_eq!(sum, 1_f32);
+            });
+        }
+        scope.spawn(move || {
+            let mut velocities = world.components()
+                .try_borrow_storage_mut::<Velocity, realm::ChunkedStorage<Velocity>>()
+                .unwrap();
+            for velocity in velocities.chunks_mut(entity_type).flatten() {
+                velocity.x = 1_f32;
+            }
+        });
+    });
+
+    let velocities = world.components()
+        .get_storage::<Velocity, realm::ChunkedStorage<Velocity>>()
+        .unwrap();
+    assert_eq!(velocities.chunks(entity_type).flatten().map(|velocity| velocity.x).sum::<f32>(), 1_f32);
+}
+
+#[test]
+fn test_world_drops_components_once() {
+    let value = std::sync::Arc::new(());
+    {
+        let mut world = realm::World::new();
+        let entities: Vec<realm::Entity> = (0..4)
+            .map(|i| world.push((value.clone(), Name(format!("entity {}", i)))))
+            .collect();
+        world.remove(entities[1]);
+
+        assert_eq!(std::sync::Arc::strong_count(&value), 4);
+
+        world.clear();
+
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+
+        world.push((value.clone(), Name(String::from("dropped with the world"))));
+    }
+
+    assert_eq!(std::sync::Arc::strong_count(&value), 1);
+}
+
+#[test]
+fn test_query_for_each_reads_and_writes_matching_entities() {
+    let mut world = realm::World::new();
+    for i in 0..3 {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(1_f32, 0_f32, 0_f32)));
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+        world.push((Velocity::new(2_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    }
+    let query = realm::Query::<(realm::Write<Position>, realm::Read<Velocity>)>::new();
+    query.for_each(&world, |(position, velocity)| position.x += velocity.x);
+
+    let mut positions = Vec::new();
+    realm::Query::<(realm::Read<Position>,)>::new().for_each(&world, |(position,)| positions.push(position.x));
+    positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    assert_eq!(positions, vec![0_f32, 1_f32, 1_f32, 2_f32, 2_f32, 3_f32]);
+}
+
+#[test]
+fn test_query_without_storage_matches_nothing() {
+    let mut world = realm::World::new();
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut count = 0;
+    realm::Query::<(realm::Read<Position>, realm::Read<Stunned>)>::new().for_each(&world, |_| count += 1);
+
+    assert_eq!(count, 0);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn test_query_conflicting_with_itself_panics() {
+    let mut world = realm::World::new();
+    world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    realm::Query::<(realm::Write<Position>, realm::Read<Position>)>::new().for_each(&world, |_| {});
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_query_par_for_each_visits_every_entity_once() {
+    let mut world = realm::World::new();
+    for i in 0..(3 * realm::PARALLEL_BATCH_SIZE + 7) {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(1_f32, 0_f32, 0_f32)));
+    }
+    for i in 0..10 {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    }
+    let query = realm::Query::<(realm::Write<Position>, realm::Read<Velocity>)>::new();
+    query.par_for_each(&world, |(position, velocity)| position.y += velocity.x);
+
+    let visited = std::sync::atomic::AtomicUsize::new(0);
+    realm::Query::<(realm::Read<Position>,)>::new().par_for_each(&world, |(position,)| {
+        if position.y == 1_f32 {
+            visited.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    });
+
+    assert_eq!(visited.into_inner(), 3 * realm::PARALLEL_BATCH_SIZE + 7);
+}
+
+#[test]
+fn test_query_chunks_zip_entities_with_component_slices() {
+    let mut world = realm::World::new();
+    let mut moving = Vec::new();
+    for i in 0..4 {
+        moving.push(world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(i as f32, 0_f32, 0_f32))));
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    }
+    let query = realm::Query::<(realm::Read<Position>, realm::Write<Velocity>)>::new();
+    let mut borrow = query.borrow(&world);
+    let mut visited = Vec::new();
+    for chunk in borrow.chunks() {
+        let (entities, (positions, velocities)) = chunk.into_parts();
+
+        assert_eq!(entities.len(), positions.len());
+        assert_eq!(entities.len(), velocities.len());
+
+        for (i, entity) in entities.iter().enumerate() {
+            assert_eq!(positions[i].x, velocities[i].x);
+            velocities[i].y = 1_f32;
+            visited.push(*entity);
+        }
+    }
+    drop(borrow);
+
+    assert_eq!(visited, moving);
+
+    let mut chunk_lengths = Vec::new();
+    realm::Query::<realm::Read<Velocity>>::new().for_each_chunk(&world, |chunk| {
+        chunk_lengths.push(chunk.len());
+
+        assert!(chunk.into_components().iter().all(|velocity| velocity.y == 1_f32));
+    });
+
+    assert_eq!(chunk_lengths, vec![4]);
+}
+
+#[test]
+fn test_query_reads_and_writes_chunked_components() {
+    let mut world = realm::World::new();
+    world.register_storage(realm::ChunkedStorage::<Position>::with_chunk_size(64));
+    for i in 0..12 {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(1_f32, 0_f32, 0_f32)));
+    }
+    let query = realm::Query::<(realm::Write<Position>, realm::Read<Velocity>)>::new();
+    query.for_each(&world, |(position, velocity)| position.x += velocity.x);
+
+    let mut positions = Vec::new();
+    let mut chunk_lengths = Vec::new();
+    realm::Query::<realm::Read<Position>>::new().for_each_chunk(&world, |chunk| {
+        chunk_lengths.push(chunk.len());
+        positions.extend(chunk.into_components().iter().map(|position| position.x));
+    });
+
+    assert_eq!(positions, (1..13).map(|i| i as f32).collect::<Vec<f32>>());
+    assert_eq!(chunk_lengths, vec![5, 5, 2]);
+}
+
+#[test]
+fn test_query_fetches_optional_sparse_components() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..4)
+        .map(|i| world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))))
+        .collect();
+    world.push((Position::new(4_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    world.insert_sparse(entities[1], Stunned(1));
+    world.insert_sparse(entities[3], Stunned(3));
+
+    let query = realm::Query::<(realm::Write<Position>, realm::Sparse<Stunned>)>::new();
+    query.for_each(&world, |(position, stunned)| {
+        if stunned.is_some() {
+            position.y = 1_f32;
+        }
+    });
+
+    let mut stunned = Vec::new();
+    realm::Query::<(realm::Read<Position>, realm::Read<Velocity>)>::new().for_each(&world, |(position, _)| {
+        stunned.push(position.y == 1_f32);
+    });
+
+    assert_eq!(stunned, vec![false, true, false, true]);
+    assert!(realm::Query::<realm::Sparse<Stunned>>::new().get(&world, entities[3]).unwrap().get().is_some());
+    assert!(realm::Query::<realm::Sparse<Name>>::new().get(&world, entities[3]).unwrap().get().is_none());
+}
+
+#[test]
+fn test_query_filters_sources_of_relations() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..4)
+        .map(|i| world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))))
+        .collect();
+    let carol = world.push((Position::new(4_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    world.add_relation(entities[3], Likes(3), carol);
+    world.add_relation(entities[1], Likes(1), carol);
+    world.add_relation(entities[2], Likes(2), entities[0]);
+    world.add_relation(carol, Likes(4), carol);
+
+    let query = realm::Query::<(realm::Write<Position>, realm::Read<Velocity>)>::new();
+    query.for_each_related(&world, realm::RelatedTo::<Likes>::new(carol), |(position, _)| {
+        position.y = 1_f32;
+    });
+
+    let mut liked = Vec::new();
+    realm::Query::<realm::Read<Position>>::new().for_each_related(&world, realm::RelatedTo::<Likes>::new(carol), |position| {
+        liked.push(position.x);
+    });
+
+    assert_eq!(liked, vec![3_f32, 1_f32, 4_f32]);
+    for (i, entity) in entities.iter().enumerate() {
+        let position = realm::Query::<realm::Read<Position>>::new().get(&world, *entity).unwrap().get().y;
+        assert_eq!(position == 1_f32, i == 1 || i == 3);
+    }
+    realm::Query::<realm::Read<Position>>::new().for_each_related(&world, realm::RelatedTo::<Name>::new(carol), |_| {
+        panic!("There are no relations of type `Name`");
+    });
+}
+
+#[test]
+fn test_prepared_query_matches_new_entity_types() {
+    let mut world = realm::World::new();
+    world.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut query = realm::PreparedQuery::<realm::Read<Position>>::new();
+    let mut sum = 0_f32;
+    query.for_each(&world, |position| sum += position.x);
+
+    assert_eq!(sum, 1_f32);
+    assert_eq!(query.entity_types().len(), 1);
+
+    world.push((Velocity::new(0_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    world.push((Position::new(2_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    let mut sum = 0_f32;
+    query.for_each(&world, |position| sum += position.x);
+
+    assert_eq!(sum, 3_f32);
+    assert_eq!(query.entity_types().len(), 2);
+}
+
+#[test]
+fn test_prepared_query_is_invalidated_by_renumbering() {
+    let mut world = realm::World::new();
+    let entity = world.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    world.push((Position::new(2_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    let mut query = realm::PreparedQuery::<realm::Read<Position>>::new();
+    query.for_each(&world, |_| {});
+    let epoch = world.layout_epoch();
+    world.remove(entity);
+    world.remove_empty_entity_types();
+
+    assert_ne!(world.layout_epoch(), epoch);
+
+    let mut positions = Vec::new();
+    query.for_each(&world, |position| positions.push(position.x));
+
+    assert_eq!(positions, vec![2_f32]);
+    assert_eq!(query.entity_types().len(), 1);
+
+    let mut other = realm::World::new();
+    other.push((Position::new(3_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    let mut positions = Vec::new();
+    query.for_each(&other, |position| positions.push(position.x));
+
+    assert_eq!(positions, vec![3_f32]);
+}
+
+#[test]
+fn test_query_get_mut_looks_up_matching_entities() {
+    let mut world = realm::World::new();
+    let moving = world.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(2_f32, 0_f32, 0_f32)));
+    let still = world.push((Position::new(3_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    let query = realm::Query::<(realm::Write<Position>, realm::Read<Velocity>)>::new();
+    let (position, velocity) = query.get_mut(&mut world, moving).unwrap();
+    position.x += velocity.x;
+
+    assert!(query.get_mut(&mut world, still).is_none());
+    assert_eq!(world.entry(moving).unwrap().get_component::<Position>().unwrap().x, 3_f32);
+
+    let mut borrow = realm::Query::<realm::Read<Position>>::new().borrow(&world);
+
+    assert_eq!(borrow.get(still).unwrap().x, 3_f32);
+}
+
+#[test]
+fn test_query_get_reads_components_through_shared_world() {
+    let mut world = realm::World::new();
+    let moving = world.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(2_f32, 0_f32, 0_f32)));
+    let resting = world.push((Position::new(3_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    let query = realm::Query::<(realm::Read<Position>, realm::Read<Velocity>)>::new();
+
+    let item = query.get(&world, moving).unwrap();
+    let other = realm::Query::<realm::Read<Position>>::new().get(&world, resting).unwrap();
+    let (position, velocity) = item.get();
+
+    assert_eq!((position.x, velocity.x), (1_f32, 2_f32));
+    assert_eq!(other.get().x, 3_f32);
+    assert!(query.get(&world, resting).is_none());
+    assert!(world.try_borrow_mut::<Position>().is_err());
+
+    drop((item, other));
+    world.remove(moving);
+
+    assert!(query.get(&world, moving).is_none());
+    assert!(world.try_borrow_mut::<Position>().is_ok());
+}
+
+#[test]
+fn test_query_get_many_mut_returns_disjoint_components() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..4)
+        .map(|i| world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))))
+        .collect();
+    let other = world.push((Position::new(9_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    let query = realm::Query::<realm::Write<Position>>::new();
+    let [a, b, c] = query.get_many_mut(&mut world, [entities[3], other, entities[1]]).unwrap();
+    std::mem::swap(&mut a.x, &mut b.x);
+    c.y = a.x + b.x;
+
+    assert!(query.get_many_mut(&mut world, [entities[0], entities[0]]).is_none());
+    assert_eq!(world.entry(entities[3]).unwrap().get_component::<Position>().unwrap().x, 9_f32);
+    assert_eq!(world.entry(other).unwrap().get_component::<Position>().unwrap().x, 3_f32);
+    assert_eq!(world.entry(entities[1]).unwrap().get_component::<Position>().unwrap().y, 12_f32);
+
+    let mut borrow = query.borrow(&world);
+    let [a, b] = borrow.get_many_mut([entities[0], entities[2]]).unwrap();
+    a.z = 1_f32;
+    b.z = 2_f32;
+}
+
+#[test]
+fn test_retain_keeps_locations_consistent() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..8)
+        .map(|i| match i % 2 {
+            0 => world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32))),
+            _ => world.push((Position::new(i as f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32))),
+        })
+        .collect();
+    world.set_parent(entities[3], entities[2]);
+    world.retain(|entry| {
+        let x = entry.get_component::<Position>().unwrap().x;
+        x == 3_f32 || x >= 6_f32
+    });
+
+    assert_eq!(world.len(), 3);
+    assert_eq!(world.parent(entities[3]), None);
+    for i in [3, 6, 7] {
+        let entry = world.entry(entities[i]).unwrap();
+
+        assert_eq!(entry.get_component::<Position>().unwrap().x, i as f32);
+    }
+    for i in [0, 1, 2, 4, 5] {
+        assert!(!world.contains(entities[i]));
+    }
+}
+
+#[test]
+fn test_drain_filter_returns_removed_entities() {
+    let mut world = realm::World::new();
+    world.set_relation_cleanup::<DockedAt>(realm::RelationCleanup::RemoveSource);
+    let station = spawn_node(&mut world, 0_f32);
+    let ship = spawn_node(&mut world, 1_f32);
+    let other = spawn_node(&mut world, 2_f32);
+    world.add_relation(ship, DockedAt, station);
+    let drained = world.drain_filter(|entry| entry.get_component::<Position>().unwrap().x == 0_f32);
+
+    assert_eq!(drained, vec![station]);
+    assert_eq!(world.len(), 1);
+    assert!(world.contains(other));
+}
+
+#[test]
+fn test_drain_filter_compacts_entity_types_in_order() {
+    let value = std::sync::Arc::new(());
+    let mut world = realm::World::new();
+    assert!(world.register_storage(realm::ChunkedStorage::<Name>::with_chunk_size(64)));
+    let entities: Vec<realm::Entity> = (0..10)
+        .map(|i| world.push((value.clone(), Name(format!("entity {}", i)))))
+        .collect();
+    let drained = world.drain_filter(|entry| entry.location().component().id() % 3 == 0);
+    let mut names = Vec::new();
+    realm::Query::<realm::Read<Name>>::new().for_each(&world, |name| names.push(name.0.clone()));
+
+    assert_eq!(drained, vec![entities[0], entities[3], entities[6], entities[9]]);
+    assert_eq!(names, vec!["entity 1", "entity 2", "entity 4", "entity 5", "entity 7", "entity 8"]);
+    assert_eq!(std::sync::Arc::strong_count(&value), 7);
+    for (i, entity) in entities.iter().enumerate().filter(|(i, _)| i % 3 != 0) {
+        let entry = world.entry(*entity).unwrap();
+
+        assert_eq!(entry.get_component::<Name>().unwrap(), &Name(format!("entity {}", i)));
+    }
+}
+
+#[test]
+fn test_drain_filter_visits_entities_moved_by_the_predicate_once() {
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..6).map(|i| spawn_node(&mut world, i as f32)).collect();
+    let mut visited = Vec::new();
+    let drained = world.drain_filter(|entry| {
+        let x = entry.get_component::<Position>().unwrap().x;
+        visited.push(x);
+        if x < 3_f32 {
+            entry.take_component::<Velocity>();
+        }
+        x == 1_f32 || x == 4_f32
+    });
+    visited.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    assert_eq!(visited, vec![0_f32, 1_f32, 2_f32, 3_f32, 4_f32, 5_f32]);
+    assert_eq!(drained.len(), 2);
+    assert!(drained.contains(&entities[1]) && drained.contains(&entities[4]));
+    for i in [0, 2, 3, 5] {
+        let entry = world.entry(entities[i]).unwrap();
+
+        assert_eq!(entry.get_component::<Position>().unwrap().x, i as f32);
+        assert_eq!(entry.has_component::<Velocity>(), i >= 3);
+    }
+}
+
+#[test]
+fn test_clear_keeps_entity_types_and_capacity() {
+    let value = std::sync::Arc::new(());
+    let mut world = realm::World::new();
+    assert!(world.register_storage(realm::ChunkedStorage::<Name>::with_chunk_size(64)));
+    let entities: Vec<realm::Entity> = (0..8)
+        .map(|i| world.push((value.clone(), Name(format!("entity {}", i)))))
+        .collect();
+    world.insert_sparse(entities[0], Stunned(1));
+    world.set_parent(entities[1], entities[0]);
+    world.add_relation(entities[2], Likes(1), entities[3]);
+    let capacity = world.memory_stats().total().capacity();
+    let layout_epoch = world.layout_epoch();
+    world.clear();
+
+    assert!(world.is_empty());
+    assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    assert_eq!(world.entity_types().len(), 1);
+    assert_eq!(world.memory_stats().total().capacity(), capacity);
+    assert_eq!(world.layout_epoch(), layout_epoch);
+    assert!(world.sparse_components().get::<Stunned>().unwrap().is_empty());
+    assert!(world.relations().get::<Likes>().unwrap().is_empty());
+    assert!(entities.iter().all(|entity| !world.contains(*entity)));
+
+    let entity = world.push((value.clone(), Name(String::from("refilled"))));
+
+    assert!(!entities.contains(&entity));
+    assert_eq!(world.entry(entity).unwrap().get_component::<Name>().unwrap().0, "refilled");
+}
+
+#[test]
+fn test_clear_with_release_drops_entity_types() {
+    let mut world = realm::World::new();
+    for i in 0..4 {
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+        world.push((Position::new(i as f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+    }
+    let layout_epoch = world.layout_epoch();
+    world.clear_with(realm::ClearMode::Release);
+
+    assert!(world.is_empty());
+    assert!(world.entity_types().is_empty());
+    assert_eq!(world.memory_stats().total().capacity(), 0);
+    assert_ne!(world.layout_epoch(), layout_epoch);
+
+    let entity = world.push((Position::new(1_f32, 0_f32, 0_f32), Acceleration::new(0_f32, 0_f32, 0_f32)));
+
+    assert_eq!(world.entity_types().len(), 1);
+    assert_eq!(world.entry(entity).unwrap().get_component::<Position>().unwrap().x, 1_f32);
+}
+
+#[test]
+fn test_take_component_moves_entity_to_smaller_entity_type() {
+    let value = std::sync::Arc::new(());
+    let mut world = realm::World::new();
+    let entities: Vec<realm::Entity> = (0..3)
+        .map(|i| world.push((Position::new(i as f32, 0_f32, 0_f32), value.clone())))
+        .collect();
+    let mut entry = world.entry(entities[0]).unwrap();
+    let taken = entry.take_component::<std::sync::Arc<()>>().unwrap();
+
+    assert!(!entry.has_component::<std::sync::Arc<()>>());
+    assert_eq!(entry.get_component::<Position>().unwrap().x, 0_f32);
+    assert!(entry.take_component::<std::sync::Arc<()>>().is_none());
+    assert_eq!(std::sync::Arc::strong_count(&value), 4);
+
+    drop(taken);
+
+    assert_eq!(std::sync::Arc::strong_count(&value), 3);
+    assert_eq!(world.entity_types().len(), 2);
+    for (i, entity) in entities.iter().enumerate() {
+        assert_eq!(world.entry(*entity).unwrap().get_component::<Position>().unwrap().x, i as f32);
+    }
+
+    let mut entry = world.entry(entities[2]).unwrap();
+    entry.take_component::<std::sync::Arc<()>>();
+
+    assert_eq!(entry.location().entity_type(), world.location(entities[0]).unwrap().entity_type());
+}
+
+#[test]
+fn test_replace_component_returns_old_value() {
+    let mut world = realm::World::new();
+    let entity = world.push((Position::new(1_f32, 0_f32, 0_f32), Name(String::from("old"))));
+    let mut entry = world.entry(entity).unwrap();
+
+    assert_eq!(entry.replace_component(Name(String::from("new"))).unwrap().0, "old");
+    assert_eq!(entry.get_component::<Name>().unwrap().0, "new");
+    assert!(entry.replace_component(Velocity::new(0_f32, 0_f32, 0_f32)).is_none());
+    assert!(!entry.has_component::<Velocity>());
+}
+
+#[test]
+fn test_swap_components_between_entity_types() {
+    let mut world = realm::World::new();
+    assert!(world.register_storage(realm::ChunkedStorage::<Name>::with_chunk_size(64)));
+    let a = world.push((Position::new(1_f32, 0_f32, 0_f32), Name(String::from("a"))));
+    let b = world.push((Velocity::new(0_f32, 0_f32, 0_f32), Name(String::from("b"))));
+    let c = world.push((Position::new(3_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+
+    assert!(world.swap_components::<Name>(a, b));
+    assert_eq!(world.entry(a).unwrap().get_component::<Name>().unwrap().0, "b");
+    assert_eq!(world.entry(b).unwrap().get_component::<Name>().unwrap().0, "a");
+    assert!(world.swap_components::<Name>(a, a));
+    assert!(!world.swap_components::<Position>(a, b));
+    assert!(world.swap_components::<Position>(a, c));
+    assert_eq!(world.entry(a).unwrap().get_component::<Position>().unwrap().x, 3_f32);
+}
+
+#[test]
+fn test_removed_entity_ids_are_not_reused() {
+    let mut world = realm::World::new();
+    let removed = world.push((Position::new(0_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+    world.insert_sparse(removed, Stunned(1));
+    world.remove(removed);
+    let entity = world.push((Position::new(1_f32, 0_f32, 0_f32), Velocity::new(0_f32, 0_f32, 0_f32)));
+
+    assert_ne!(entity, removed);
+    assert!(!world.contains(removed));
+    assert_eq!(world.get_sparse::<Stunned>(removed), None);
+}